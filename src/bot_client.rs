@@ -0,0 +1,131 @@
+use super::*;
+
+use common_model::{DeltaDecoder, Event, Food, FoodEvent, ModelMessage, Player};
+
+/// Bot playing through the network protocol, same as a human client does
+pub struct BotClient {
+    connection: connection::Connection,
+    brain: Box<dyn BotBrain>,
+    delta: DeltaDecoder,
+    food: Vec<Food>,
+    input_seq: u64,
+}
+
+impl BotClient {
+    pub fn run(net_opts: &NetOpts, count: usize, personality: &BotPersonality, encoding: Encoding) {
+        let threads: Vec<_> = (1..=count)
+            .map(|index| {
+                let addr = net_opts.addr.clone();
+                let personality = personality.clone();
+                std::thread::spawn(move || {
+                    let name = format!("NetBot#{} ({})", index, personality.name);
+                    let connection = match futures::executor::block_on(connection::connect(&addr)) {
+                        Ok(connection) => connection,
+                        Err(e) => {
+                            error!("{:?} failed to connect: {:#}", name, e);
+                            return;
+                        }
+                    };
+                    let mut bot = Self {
+                        connection,
+                        brain: personality.brain(),
+                        delta: DeltaDecoder::new(),
+                        food: Vec::new(),
+                        input_seq: 0,
+                    };
+                    if !bot.handshake() {
+                        return;
+                    }
+                    bot.connection.send(ClientMessage::SetEncoding(encoding));
+                    bot.connection.send(ClientMessage::SetName(name.clone()));
+                    bot.play();
+                    info!("{:?} disconnected", name);
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().expect("Bot thread panicked");
+        }
+    }
+
+    fn handshake(&mut self) -> bool {
+        self.connection.send(ClientMessage::Hello(Hello::current()));
+        match futures::executor::block_on(self.connection.next()) {
+            Some(ServerMessage::Welcome(hello)) => {
+                info!("Connected to server build {}", hello.build);
+                true
+            }
+            Some(ServerMessage::Rejected(reason)) => {
+                error!("Server rejected the bot: {}", reason);
+                false
+            }
+            Some(message) => {
+                error!("Expected welcome from the server, got {:?}", message);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Answers every state update pushed by the server with an action
+    fn play(&mut self) {
+        while let Some(message) = futures::executor::block_on(self.connection.next()) {
+            let message = match message {
+                ServerMessage::Update(update) => update,
+                ServerMessage::ResumeToken(_)
+                | ServerMessage::RoomJoined(_)
+                | ServerMessage::Queue(_)
+                | ServerMessage::Notice(_) => continue,
+                ServerMessage::Rejected(reason) => {
+                    warn!("Server dropped the bot: {}", reason);
+                    return;
+                }
+                message => {
+                    warn!("Unexpected message {:?}", message);
+                    continue;
+                }
+            };
+            let model = self.delta.decode(message.model);
+            for event in message.events {
+                if let Event::Food(event) = event {
+                    match event {
+                        FoodEvent::Add(food) => self.food.push(food),
+                        FoodEvent::Remove(id) | FoodEvent::OutOfView(id) => {
+                            self.food.retain(|food| food.id != id)
+                        }
+                    }
+                }
+            }
+            let view_time = model.as_ref().map(|model| model.current_time);
+            let action = match model {
+                Some(model) => self.think(&model, message.client_player_id),
+                None => Action::default(),
+            };
+            self.input_seq += 1;
+            self.connection.send(ClientMessage::Action {
+                action,
+                seq: self.input_seq,
+                view_time,
+                ack: self.delta.ack(),
+            });
+        }
+    }
+
+    fn think(&mut self, model: &ModelMessage, player_id: Id) -> Action {
+        let me: &Player = match model.players.get(&player_id) {
+            Some(me) => me,
+            None => {
+                self.connection.send(ClientMessage::Spawn);
+                return Action::default();
+            }
+        };
+        let view = BotView {
+            rules: &model.rules,
+            current_time: model.current_time,
+            players: &model.players,
+            projectiles: &model.projectiles,
+            food: &self.food,
+        };
+        self.brain.think(&view, me)
+    }
+}
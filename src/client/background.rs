@@ -39,6 +39,10 @@ impl Background {
             particles,
         }
     }
+    /// Whether the background covers a world of given rules, which may change between rounds
+    pub fn fits(&self, rules: &Rules) -> bool {
+        self.rules.world_size == rules.world_size
+    }
     pub fn update(&mut self, delta_time: f32) {
         for p in &mut self.particles {
             p.pos = self.rules.normalize_pos(p.pos + p.vel * delta_time);
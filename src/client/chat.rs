@@ -0,0 +1,250 @@
+use super::*;
+
+use common_model::ChatChannel;
+
+/// Chat input box and the log of recent messages
+pub struct Chat {
+    /// Text being typed, the input box is closed if not set
+    pub input: Option<String>,
+    /// Index into `PREFIXES` of the command the typed text is sent with
+    prefix: usize,
+    /// Recent messages with how long ago they arrived
+    log: VecDeque<(String, Color<f32>, f32)>,
+    #[cfg(target_arch = "wasm32")]
+    typed: TypedText,
+}
+
+impl Chat {
+    const MAX_LINES: usize = 8;
+    /// How long a message stays fully visible, in seconds
+    const SHOW_TIME: f32 = 10.0;
+    const FADE_TIME: f32 = 2.0;
+    const FONT_SIZE: f32 = 16.0;
+    /// Commands can be typed with a slash too, except in the native client,
+    /// where geng does not tell punctuation keys apart
+    const PREFIXES: [(&'static str, &'static str); 6] = [
+        ("", "all"),
+        ("/t ", "team"),
+        ("/w ", "whisper"),
+        ("/start", "host: start match"),
+        ("/kick ", "host: kick"),
+        ("/rules ", "host: rules"),
+    ];
+
+    pub fn new() -> Self {
+        Self {
+            input: None,
+            prefix: 0,
+            log: VecDeque::new(),
+            #[cfg(target_arch = "wasm32")]
+            typed: TypedText::new(),
+        }
+    }
+
+    /// Adds a message to the log, `sent_to` is the receiver of a whisper sent by this client
+    pub fn push(&mut self, name: &str, text: &str, channel: &ChatChannel, sent_to: Option<&str>) {
+        let (line, color) = match channel {
+            ChatChannel::All => (format!("{}: {}", name, text), Color::rgb(1.0, 1.0, 1.0)),
+            ChatChannel::Team => (
+                format!("[team] {}: {}", name, text),
+                Color::rgb(0.5, 0.8, 1.0),
+            ),
+            ChatChannel::Whisper(_) => (
+                match sent_to {
+                    Some(to) => format!("[to {}] {}", to, text),
+                    None => format!("[from {}] {}", name, text),
+                },
+                Color::rgb(1.0, 0.6, 1.0),
+            ),
+        };
+        self.push_line(line, color);
+    }
+
+    fn push_line(&mut self, line: String, color: Color<f32>) {
+        self.log.push_back((line, color, 0.0));
+        while self.log.len() > Self::MAX_LINES {
+            self.log.pop_front();
+        }
+    }
+
+    /// Adds a message from the server itself
+    pub fn notice(&mut self, text: &str) {
+        self.push_line(text.to_owned(), Color::rgb(1.0, 1.0, 0.5));
+    }
+
+    pub fn update(&mut self, delta_time: f32) {
+        for (_, _, age) in &mut self.log {
+            *age += delta_time;
+        }
+        #[cfg(target_arch = "wasm32")]
+        {
+            let typed = self.typed.take();
+            if let Some(input) = &mut self.input {
+                input.push_str(&typed);
+            }
+        }
+    }
+
+    /// Handles a key while typing, returning the message to send once Enter is pressed
+    pub fn handle_key(&mut self, key: geng::Key, shift: bool) -> Option<String> {
+        let input = self.input.as_mut()?;
+        // Typed before this key, so it goes in first
+        #[cfg(target_arch = "wasm32")]
+        input.push_str(&self.typed.take());
+        match key {
+            geng::Key::Enter => {
+                let text = self.input.take().unwrap();
+                let prefix = Self::PREFIXES[self.prefix].0;
+                self.prefix = 0;
+                if !text.trim().is_empty() || prefix == "/start" {
+                    return Some(format!("{}{}", prefix, text));
+                }
+            }
+            geng::Key::Escape => {
+                self.input = None;
+                self.prefix = 0;
+            }
+            geng::Key::Backspace => {
+                input.pop();
+            }
+            geng::Key::Up => self.prefix = (self.prefix + 1) % Self::PREFIXES.len(),
+            geng::Key::Down => {
+                self.prefix = (self.prefix + Self::PREFIXES.len() - 1) % Self::PREFIXES.len()
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            _ => {
+                if let Some(c) = key_char(key, shift) {
+                    input.push(c);
+                }
+            }
+            // Characters come from the page, see `TypedText`
+            #[cfg(target_arch = "wasm32")]
+            _ => {}
+        }
+        None
+    }
+
+    /// Draws the log above the bottom left corner, with the input box under it
+    pub fn draw(&self, font: &geng::Font, framebuffer: &mut ugli::Framebuffer) {
+        let mut y = 30.0;
+        if let Some(input) = &self.input {
+            font.draw(
+                framebuffer,
+                &format!("({}) {}_", Self::PREFIXES[self.prefix].1, input),
+                vec2(10.0, y),
+                Self::FONT_SIZE,
+                Color::rgb(1.0, 1.0, 0.5),
+            );
+            y += Self::FONT_SIZE * 1.5;
+        }
+        for (line, color, age) in self.log.iter().rev() {
+            let alpha = if self.input.is_some() {
+                1.0
+            } else {
+                1.0 - ((age - Self::SHOW_TIME) / Self::FADE_TIME).clamp(0.0, 1.0)
+            };
+            if alpha > 0.0 {
+                let mut color = *color;
+                color.a = alpha;
+                font.draw(framebuffer, line, vec2(10.0, y), Self::FONT_SIZE, color);
+            }
+            y += Self::FONT_SIZE;
+        }
+    }
+}
+
+/// Turns host commands typed into chat into their messages, with players looked up by name
+pub fn host_command<'a>(
+    text: &str,
+    mut names: impl Iterator<Item = (Id, &'a str)>,
+) -> Option<Result<ClientMessage, String>> {
+    let command = text.strip_prefix('/')?;
+    let (name, rest) = command.split_once(' ').unwrap_or((command, ""));
+    let rest = rest.trim();
+    Some(match name {
+        "start" => Ok(ClientMessage::StartMatch),
+        "kick" => names
+            .find(|(_, name)| name.eq_ignore_ascii_case(rest))
+            .map(|(id, _)| ClientMessage::Kick(id))
+            .ok_or_else(|| format!("There is no player {:?}", rest)),
+        "rules" => match rest.split_once(' ') {
+            Some((name, value)) => Ok(ClientMessage::SetRule {
+                name: name.to_owned(),
+                value: value.trim().to_owned(),
+            }),
+            None => Err("Usage: /rules name value".to_owned()),
+        },
+        _ => return None,
+    })
+}
+
+/// Character typed by a letter, digit or space key, shifted digits giving symbols as on US keyboards.
+///
+/// Other punctuation keys all come from geng as `Key::Unknown`, so they can not be typed.
+#[cfg(not(target_arch = "wasm32"))]
+fn key_char(key: geng::Key, shift: bool) -> Option<char> {
+    if key == geng::Key::Space {
+        return Some(' ');
+    }
+    let name = format!("{:?}", key);
+    let mut chars = name.strip_prefix("Num").unwrap_or(&name).chars();
+    let c = match (chars.next(), chars.next()) {
+        (Some(c), None) => c,
+        _ => return None,
+    };
+    Some(match (c.to_digit(10), shift) {
+        (Some(digit), true) => b")!@#$%^&*("[digit as usize] as char,
+        (Some(_), false) => c,
+        (None, true) => c,
+        (None, false) => c.to_ascii_lowercase(),
+    })
+}
+
+/// Characters typed into the page, which the browser knows for any keyboard layout
+#[cfg(target_arch = "wasm32")]
+struct TypedText {
+    typed: Rc<RefCell<String>>,
+    on_key: Closure<dyn FnMut(web_sys::KeyboardEvent)>,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl TypedText {
+    fn new() -> Self {
+        let typed = Rc::new(RefCell::new(String::new()));
+        let on_key = Closure::wrap(Box::new({
+            let typed = typed.clone();
+            move |event: web_sys::KeyboardEvent| {
+                // Named keys like Enter are longer than a character, shortcuts are not text
+                let key = event.key();
+                if key.chars().count() == 1 && !event.ctrl_key() && !event.meta_key() {
+                    typed.borrow_mut().push_str(&key);
+                }
+            }
+        }) as Box<dyn FnMut(web_sys::KeyboardEvent)>);
+        if let Some(document) = web_sys::window().and_then(|window| window.document()) {
+            if let Err(e) = document
+                .add_event_listener_with_callback("keydown", on_key.as_ref().unchecked_ref())
+            {
+                warn!("Failed to listen for typed text: {:?}", e);
+            }
+        }
+        Self { typed, on_key }
+    }
+
+    /// Text typed since last call
+    fn take(&self) -> String {
+        std::mem::take(&mut *self.typed.borrow_mut())
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Drop for TypedText {
+    fn drop(&mut self) {
+        if let Some(document) = web_sys::window().and_then(|window| window.document()) {
+            let _ = document.remove_event_listener_with_callback(
+                "keydown",
+                self.on_key.as_ref().unchecked_ref(),
+            );
+        }
+    }
+}
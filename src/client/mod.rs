@@ -1,15 +1,23 @@
 use super::*;
 
 mod background;
+mod chat;
 mod circle_renderer;
 mod model;
+mod prediction;
+mod replay_player;
 mod texture_renderer;
 
 use background::Background;
+use chat::Chat;
 use circle_renderer::CircleRenderer;
 use model::*;
+use prediction::Prediction;
+use replay_player::ReplayPlayer;
 use texture_renderer::TextureRenderer;
 
+use std::collections::VecDeque;
+
 #[derive(geng::Assets)]
 pub struct Assets {
     #[asset(path = "aim.wav")]
@@ -26,6 +34,82 @@ pub struct Assets {
     music: geng::Sound,
 }
 
+/// Where the messages shown by the client come from
+enum Source {
+    Server(connection::Connection),
+    Replay(ReplayPlayer),
+    /// Server refused to talk to us, for given reason
+    Rejected(String),
+    /// Connection got lost, trying to get back into the same session
+    Reconnecting {
+        /// Attempt in progress, none while waiting to make the next one
+        connecting: Option<future::LocalBoxFuture<'static, anyhow::Result<Source>>>,
+        attempts: u32,
+        /// Time since the attempt was made or failed
+        timer: Timer,
+    },
+}
+
+/// What is needed to connect to the server again
+#[derive(Clone)]
+struct ServerInfo {
+    addr: String,
+    name: String,
+    encoding: Encoding,
+    /// Private room to join on the first connection
+    invite: Option<String>,
+    /// Rules of the private room to make on the first connection
+    create_room: Option<Rules>,
+    admin_key: Option<String>,
+}
+
+/// Says hello and joins the game, taking the old player back if given a resume token.
+///
+/// Fails if the server can not be reached, refusals of the server give `Source::Rejected`.
+fn connect(
+    server: ServerInfo,
+    spectate: bool,
+    resume: Option<String>,
+) -> future::LocalBoxFuture<'static, anyhow::Result<Source>> {
+    async move {
+        let mut connection = connection::connect(&server.addr).await?;
+        connection.send(ClientMessage::Hello(Hello::current()));
+        match connection.next().await {
+            Some(ServerMessage::Welcome(hello)) => {
+                info!("Connected to server build {}", hello.build);
+            }
+            Some(ServerMessage::Rejected(reason)) => return Ok(Source::Rejected(reason)),
+            Some(message) => {
+                return Ok(Source::Rejected(format!(
+                    "Unexpected message from the server: {:?}",
+                    message
+                )))
+            }
+            None => anyhow::bail!("Connection closed during handshake"),
+        }
+        match resume {
+            Some(token) => connection.send(ClientMessage::Resume(token)),
+            None => {
+                if let Some(rules) = server.create_room {
+                    connection.send(ClientMessage::CreateRoom(rules));
+                } else if let Some(code) = server.invite {
+                    connection.send(ClientMessage::JoinRoom(code));
+                }
+            }
+        }
+        if let Some(key) = server.admin_key {
+            connection.send(ClientMessage::AdminLogin(key));
+        }
+        connection.send(ClientMessage::SetEncoding(server.encoding));
+        if spectate {
+            connection.send(ClientMessage::Spectate);
+        }
+        connection.send(ClientMessage::SetName(server.name));
+        Ok(Source::Server(connection))
+    }
+    .boxed_local()
+}
+
 pub struct ClientApp {
     geng: Rc<Geng>,
     sound_player: Rc<SoundPlayer>,
@@ -35,11 +119,30 @@ pub struct ClientApp {
     texture_renderer: TextureRenderer,
     background: Option<Background>,
     action: Action,
+    /// Sequence number of the latest input sent
+    input_seq: u64,
+    /// Time left until the next input is sent
+    next_input: f64,
     camera_pos: Vec2<f32>,
+    /// Player followed by the camera when spectating, free camera if not set
+    follow: Option<Id>,
     model: Model,
+    delta: common_model::DeltaDecoder,
     player_names: HashMap<Id, (String, ugli::Texture)>,
+    chat: Chat,
+    /// Id of the room we are in
+    room: Option<String>,
+    /// Room list shown to pick a room to join, empty until the server replies
+    room_list: Option<Vec<common_model::RoomInfo>>,
+    /// Place in the join queue of a full server
+    queue_position: Option<usize>,
     mouse_pos: Vec2<f32>,
-    connection: net::client::Connection<ServerMessage, ClientMessage>,
+    source: Source,
+    /// Where to reconnect to, not set when watching a replay
+    server: Option<ServerInfo>,
+    resume_token: Option<String>,
+    /// Time since anything came from the server, or since reconnecting started
+    last_message: Timer,
     traffic_watch: TrafficWatch,
     ping_watch: PingWatch,
     font: geng::Font,
@@ -50,23 +153,49 @@ pub struct ClientApp {
 
 impl ClientApp {
     pub fn run(opts: &Opts, net_opts: &NetOpts) {
+        // Options are checked before the window opens, so a mistake does not flash one
+        let server = match opts.replay {
+            Some(_) => None,
+            None => Some(ServerInfo {
+                addr: net_opts.addr.clone(),
+                name: opts.name.clone(),
+                encoding: opts.encoding,
+                invite: opts.invite.clone(),
+                create_room: opts.create_room.then(|| match &opts.room_rules {
+                    #[cfg(not(target_arch = "wasm32"))]
+                    Some(path) => config::or_exit(config::load_rules(path)),
+                    _ => default(),
+                }),
+                admin_key: opts.admin_key.clone(),
+            }),
+        };
         let geng = Rc::new(Geng::new(geng::ContextOptions {
             title: "lifeshot.io".to_owned(),
             ..default()
         }));
-        let name = opts.name.clone();
-        let connection_future = net::client::connect(&net_opts.addr);
+        let source_future = match &opts.replay {
+            Some(path) => {
+                let replay = ReplayPlayer::load(path)
+                    .unwrap_or_else(|e| panic!("Failed to load replay {:?}: {}", path, e));
+                future::ready(Source::Replay(replay)).boxed_local()
+            }
+            None => connect(server.clone().unwrap(), opts.spectate, None)
+                .map(|result| {
+                    result.unwrap_or_else(|e| {
+                        error!("{:#}", e);
+                        Source::Rejected(format!("Failed to connect to the server: {:#}", e))
+                    })
+                })
+                .boxed_local(),
+        };
         let assets_future = <Assets as geng::LoadAsset>::load(&geng, ".");
         let app = geng::LoadingScreen::new(
             &geng,
             geng::EmptyLoadingScreen,
-            future::join(assets_future, connection_future),
+            future::join(assets_future, source_future),
             {
                 let geng = geng.clone();
-                move |(assets, mut connection)| {
-                    connection.send(ClientMessage::SetName(name));
-                    Self::new(&geng, connection, assets.unwrap())
-                }
+                move |(assets, source)| Self::new(&geng, source, server, assets.unwrap())
             },
         );
         geng::run(geng, app);
@@ -142,6 +271,7 @@ impl SoundPlayer {
 struct TrafficWatch {
     inbound: (usize, usize),
     outbound: (usize, usize),
+    full_snapshots: (usize, usize),
     timer: Timer,
 }
 
@@ -150,28 +280,39 @@ impl TrafficWatch {
         Self {
             inbound: (0, 0),
             outbound: (0, 0),
+            full_snapshots: (0, 0),
             timer: Timer::new(),
         }
     }
-    fn update(&mut self, traffic: &net::Traffic) {
+    fn update(&mut self, traffic: &connection::Traffic, full_snapshots: usize) {
         if self.timer.elapsed() > 1.0 {
             fn fmt((prev, cur): (usize, usize)) -> String {
-                format!("{}KB/s", (cur - prev) / 1024)
+                format!("{:.1}KB/s", (cur - prev) as f64 / 1024.0)
             }
-            debug!("in: {}, out: {}", fmt(self.inbound), fmt(self.outbound));
+            debug!(
+                "in: {}, out: {}, full snapshots: {}",
+                fmt(self.inbound),
+                fmt(self.outbound),
+                self.full_snapshots.1 - self.full_snapshots.0,
+            );
             self.timer.tick();
             self.inbound.0 = traffic.inbound();
             self.outbound.0 = traffic.outbound();
+            self.full_snapshots.0 = full_snapshots;
         }
         self.inbound.1 = traffic.inbound();
         self.outbound.1 = traffic.outbound();
+        self.full_snapshots.1 = full_snapshots;
     }
 }
 
+/// Measures time from sending an input until a state update with it applied arrives
 struct PingWatch {
     min: f64,
     max: f64,
-    ping_timer: Timer,
+    clock: Timer,
+    /// Inputs not yet applied by the server, with times they were sent at
+    sent: VecDeque<(u64, f64)>,
     timer: Timer,
     text: String,
 }
@@ -181,13 +322,28 @@ impl PingWatch {
         Self {
             min: 100.0,
             max: 0.0,
-            ping_timer: Timer::new(),
+            clock: Timer::new(),
+            sent: VecDeque::new(),
             timer: Timer::new(),
             text: "ping".to_owned(),
         }
     }
-    fn update(&mut self) {
-        let ping = self.ping_timer.tick();
+    fn sent(&mut self, seq: u64) {
+        self.sent.push_back((seq, self.clock.elapsed()));
+    }
+    fn applied(&mut self, seq: u64) {
+        let mut sent_time = None;
+        while let Some(&(sent_seq, time)) = self.sent.front() {
+            if sent_seq > seq {
+                break;
+            }
+            sent_time = Some(time);
+            self.sent.pop_front();
+        }
+        let ping = match sent_time {
+            Some(time) => self.clock.elapsed() - time,
+            None => return,
+        };
         self.min = partial_min(self.min, ping);
         self.max = partial_max(self.max, ping);
         if self.timer.elapsed() > 1.0 {
@@ -258,17 +414,26 @@ impl UiState {
 
 impl ClientApp {
     const CAMERA_FOV: f32 = 30.0;
+    /// Inputs sent to the server per second, no matter how often updates arrive
+    const INPUT_RATE: f64 = 30.0;
+    /// Silence from the server after which the connection is considered lost, in seconds
+    const CONNECTION_TIMEOUT: f64 = 5.0;
+    /// Wait before the second attempt to reconnect, doubled after every failed one
+    const RECONNECT_DELAY: f64 = 0.5;
+    const MAX_RECONNECT_DELAY: f64 = 8.0;
+    /// Failed attempts to reconnect after which the client gives up
+    const MAX_RECONNECT_ATTEMPTS: u32 = 10;
 
-    pub fn new(
+    fn new(
         geng: &Rc<Geng>,
-        mut connection: net::client::Connection<ServerMessage, ClientMessage>,
+        source: Source,
+        server: Option<ServerInfo>,
         mut assets: Assets,
     ) -> Self {
         assets.music.looped = true;
         let assets = Rc::new(assets);
         let sound_player = Rc::new(SoundPlayer::new());
         let action = Action::default();
-        connection.send(ClientMessage::Action(action.clone()));
         Self {
             geng: geng.clone(),
             sound_player: sound_player.clone(),
@@ -278,10 +443,21 @@ impl ClientApp {
             circle_renderer: CircleRenderer::new(geng),
             texture_renderer: TextureRenderer::new(geng),
             action,
+            input_seq: 0,
+            next_input: 0.0,
             camera_pos: vec2(0.0, 0.0),
+            follow: None,
             model: Model::new(&assets, &sound_player),
+            delta: common_model::DeltaDecoder::new(),
             player_names: HashMap::new(),
-            connection,
+            chat: Chat::new(),
+            room: None,
+            room_list: None,
+            queue_position: None,
+            source,
+            server,
+            resume_token: None,
+            last_message: Timer::new(),
             traffic_watch: TrafficWatch::new(),
             ping_watch: PingWatch::new(),
             mouse_pos: vec2(0.0, 0.0),
@@ -297,6 +473,149 @@ impl ClientApp {
     }
 }
 
+impl ClientApp {
+    const REPLAY_SEEK_STEP: f64 = 5.0;
+
+    fn set_follow(&mut self, follow: Option<Id>) {
+        self.follow = follow;
+        if let Source::Server(connection) = &mut self.source {
+            connection.send(ClientMessage::Follow(follow));
+        }
+    }
+
+    /// Gets back into the same session over a new connection, gives up without a resume token
+    fn reconnect(&mut self) {
+        let (server, token) = match (&self.server, &self.resume_token) {
+            (Some(server), Some(token)) => (server.clone(), token.clone()),
+            _ => {
+                self.source = Source::Rejected("Lost connection to the server".to_owned());
+                return;
+            }
+        };
+        warn!("Lost connection to the server, reconnecting");
+        self.source = Source::Reconnecting {
+            connecting: None,
+            attempts: 0,
+            timer: Timer::new(),
+        };
+        self.last_message = Timer::new();
+    }
+
+    /// How long to wait before making given attempt to reconnect, the first one is made at once
+    fn reconnect_delay(attempt: u32) -> f64 {
+        match attempt {
+            0 => 0.0,
+            _ => (Self::RECONNECT_DELAY * 2f64.powi(attempt as i32 - 1))
+                .min(Self::MAX_RECONNECT_DELAY),
+        }
+    }
+
+    fn reconnected(&mut self, mut source: Source) {
+        if let Source::Server(connection) = &mut source {
+            info!("Reconnected to the server");
+            if self.follow.is_some() {
+                connection.send(ClientMessage::Follow(self.follow));
+            }
+        }
+        self.source = source;
+        self.model = Model::new(&self.assets, &self.sound_player);
+        self.delta = common_model::DeltaDecoder::new();
+        self.traffic_watch = TrafficWatch::new();
+        self.last_message = Timer::new();
+    }
+
+    /// Starts over in the world of another room
+    fn entered_room(&mut self, id: String) {
+        info!("Joined room {:?}", id);
+        self.chat.notice(&format!("Joined room {}", id));
+        self.room = Some(id);
+        self.room_list = None;
+        self.model = Model::new(&self.assets, &self.sound_player);
+        self.delta = common_model::DeltaDecoder::new();
+        self.player_names.clear();
+        self.follow = None;
+        self.background = None;
+    }
+
+    fn join_room(&mut self, index: usize) {
+        let id = match self.room_list.as_ref().and_then(|rooms| rooms.get(index)) {
+            Some(room) => room.id.clone(),
+            None => return,
+        };
+        if let Source::Server(connection) = &mut self.source {
+            connection.send(ClientMessage::JoinRoom(id));
+        }
+        self.room_list = None;
+    }
+
+    /// Goes through everyone on the scoreboard, since far away players are not known to the client
+    fn cycle_follow(&mut self, delta: isize) {
+        let mut ids: Vec<Id> = self.model.scores.keys().copied().collect();
+        if ids.is_empty() {
+            return;
+        }
+        ids.sort();
+        let index = match self
+            .follow
+            .and_then(|id| ids.iter().position(|&other| other == id))
+        {
+            Some(index) => (index as isize + delta).rem_euclid(ids.len() as isize) as usize,
+            None => 0,
+        };
+        self.set_follow(Some(ids[index]));
+    }
+
+    /// Whether the viewer is watching others instead of playing
+    fn spectating(&self) -> bool {
+        match self.source {
+            Source::Server(_) | Source::Reconnecting { .. } => self
+                .client_player_id
+                .is_some_and(|id| self.model.spectators.contains(&id)),
+            Source::Replay(_) => true,
+            Source::Rejected(_) => false,
+        }
+    }
+
+    fn camera_target(&self) -> Option<Id> {
+        if self.spectating() {
+            self.follow
+        } else {
+            self.client_player_id
+        }
+    }
+
+    fn handle_spectator_key(&mut self, key: geng::Key) {
+        match key {
+            geng::Key::Q => self.cycle_follow(-1),
+            geng::Key::E => self.cycle_follow(1),
+            geng::Key::C => self.set_follow(None),
+            _ => {}
+        }
+    }
+
+    fn handle_replay_key(&mut self, key: geng::Key) {
+        let replay = match &mut self.source {
+            Source::Replay(replay) => replay,
+            _ => return,
+        };
+        let mut seek = None;
+        match key {
+            geng::Key::Space => replay.paused = !replay.paused,
+            geng::Key::Left => seek = Some(replay.time - Self::REPLAY_SEEK_STEP),
+            geng::Key::Right => seek = Some(replay.time + Self::REPLAY_SEEK_STEP),
+            geng::Key::Up => replay.speed = (replay.speed * 2.0).min(ReplayPlayer::MAX_SPEED),
+            geng::Key::Down => replay.speed = (replay.speed / 2.0).max(ReplayPlayer::MIN_SPEED),
+            _ => {}
+        }
+        if let Some(time) = seek {
+            if replay.seek(time) {
+                self.model = Model::new(&self.assets, &self.sound_player);
+                self.delta = common_model::DeltaDecoder::new();
+            }
+        }
+    }
+}
+
 impl geng::State for ClientApp {
     fn update(&mut self, delta_time: f64) {
         self.ui_controller
@@ -305,12 +624,116 @@ impl geng::State for ClientApp {
         if let Some(music) = &mut self.music {
             music.set_volume(self.ui_state.volume());
         }
-        self.traffic_watch.update(self.connection.traffic());
         self.sound_player.inner.pos.set(self.camera_pos);
         {
-            let mut got = false;
-            for message in self.connection.new_messages() {
-                got = true;
+            let mut lost = false;
+            let mut reconnected = None;
+            let mut entered_room = None;
+            let mut rejected = None;
+            let messages: Vec<StateUpdate> = match &mut self.source {
+                Source::Server(connection) => {
+                    self.traffic_watch
+                        .update(connection.traffic(), self.delta.full_snapshots);
+                    let mut messages = Vec::new();
+                    while let Some(message) = connection.next().now_or_never() {
+                        let message = match message {
+                            Some(message) => message,
+                            None => {
+                                lost = true;
+                                break;
+                            }
+                        };
+                        self.last_message.tick();
+                        match message {
+                            ServerMessage::Update(update) => messages.push(update),
+                            ServerMessage::ResumeToken(token) => self.resume_token = Some(token),
+                            ServerMessage::RoomJoined(id) => {
+                                // Updates of the old world are no use anymore
+                                messages.clear();
+                                entered_room = Some(id);
+                            }
+                            ServerMessage::Rooms(rooms) => self.room_list = Some(rooms),
+                            ServerMessage::Notice(text) => self.chat.notice(&text),
+                            ServerMessage::Queue(position) => self.queue_position = position,
+                            ServerMessage::Rejected(reason) => {
+                                rejected = Some(reason);
+                                break;
+                            }
+                            message => warn!("Unexpected message {:?}", message),
+                        }
+                    }
+                    if self.last_message.elapsed() > Self::CONNECTION_TIMEOUT {
+                        lost = true;
+                    }
+                    messages
+                }
+                Source::Reconnecting {
+                    connecting,
+                    attempts,
+                    timer,
+                } => {
+                    match connecting {
+                        Some(future) => {
+                            let failure = match future.as_mut().now_or_never() {
+                                Some(Ok(source)) => {
+                                    reconnected = Some(source);
+                                    None
+                                }
+                                Some(Err(e)) => Some(format!("{:#}", e)),
+                                None if timer.elapsed() > Self::CONNECTION_TIMEOUT => {
+                                    Some("timed out".to_owned())
+                                }
+                                None => None,
+                            };
+                            if let Some(failure) = failure {
+                                warn!("Failed to reconnect: {}", failure);
+                                // Dropping the attempt hangs up if it is still going
+                                *connecting = None;
+                                *attempts += 1;
+                                *timer = Timer::new();
+                                if *attempts >= Self::MAX_RECONNECT_ATTEMPTS {
+                                    rejected = Some("Lost connection to the server".to_owned());
+                                }
+                            }
+                        }
+                        None => {
+                            if timer.elapsed() > Self::reconnect_delay(*attempts) {
+                                let server = self.server.clone().unwrap();
+                                let token = self.resume_token.clone();
+                                *connecting = Some(connect(server, false, token));
+                                *timer = Timer::new();
+                            }
+                        }
+                    }
+                    Vec::new()
+                }
+                Source::Replay(replay) => {
+                    replay.update(delta_time);
+                    let messages = replay.new_messages(self.follow);
+                    if messages.len() > 1 {
+                        // Catching up after seeking, no need to play all the sounds
+                        self.sound_player.inner.volume.set(0.0);
+                    }
+                    messages
+                }
+                Source::Rejected(_) => Vec::new(),
+            };
+            if let Some(reason) = rejected {
+                warn!("Server dropped us: {}", reason);
+                self.source = Source::Rejected(reason);
+            } else if lost {
+                self.reconnect();
+            }
+            if let Some(source) = reconnected {
+                self.reconnected(source);
+            }
+            if let Some(id) = entered_room {
+                self.entered_room(id);
+            }
+            for message in messages {
+                if let Some(seq) = message.last_input {
+                    self.ping_watch.applied(seq);
+                }
                 self.client_player_id = Some(message.client_player_id);
                 for event in &message.events {
                     if let common_model::Event::PlayerName { player_id, name } = event {
@@ -344,55 +767,146 @@ impl geng::State for ClientApp {
                         self.player_names
                             .insert(*player_id, (name.to_owned(), texture));
                     }
+                    if let common_model::Event::Chat {
+                        sender,
+                        text,
+                        channel,
+                        ..
+                    } = event
+                    {
+                        let names = &self.player_names;
+                        let name_of = |id: &Id| names.get(id).map(|(name, _)| name.as_str());
+                        let sent_to = match channel {
+                            common_model::ChatChannel::Whisper(to)
+                                if *sender == message.client_player_id =>
+                            {
+                                Some(name_of(to).unwrap_or("?"))
+                            }
+                            _ => None,
+                        };
+                        self.chat.push(
+                            name_of(sender).unwrap_or("<noname>"),
+                            text,
+                            channel,
+                            sent_to,
+                        );
+                    }
                 }
-                self.model.recv(message);
-                if self.background.is_none() {
+                self.model.client_player_id = Some(message.client_player_id);
+                if let Some(model) = self.delta.decode(message.model) {
+                    self.model.recv_model(model, message.last_input);
+                }
+                self.model.recv_events(message.events);
+                if !self
+                    .background
+                    .as_ref()
+                    .is_some_and(|background| background.fits(&self.model.rules))
+                {
                     self.background = Some(Background::new(&self.model.rules));
                 }
             }
-            if got {
-                self.ping_watch.update();
-                self.connection
-                    .send(ClientMessage::Action(self.action.clone()));
-            }
         }
         let rules = &self.model.rules;
         if let Some(background) = &mut self.background {
             background.update(delta_time as f32);
         }
         self.model.update(delta_time as f32);
+        self.chat.update(delta_time as f32);
         {
-            let mut action = &mut self.action;
-            action.target_vel = vec2(0.0, 0.0);
+            let mut direction = vec2(0.0, 0.0);
             if self.geng.window().is_key_pressed(geng::Key::W) {
-                action.target_vel.y += 1.0;
+                direction.y += 1.0;
             }
             if self.geng.window().is_key_pressed(geng::Key::A) {
-                action.target_vel.x -= 1.0;
+                direction.x -= 1.0;
             }
             if self.geng.window().is_key_pressed(geng::Key::S) {
-                action.target_vel.y -= 1.0;
+                direction.y -= 1.0;
             }
             if self.geng.window().is_key_pressed(geng::Key::D) {
-                action.target_vel.x += 1.0;
+                direction.x += 1.0;
+            }
+            let typing = self.chat.input.is_some();
+            if typing {
+                direction = vec2(0.0, 0.0);
+            }
+            if self.spectating() && self.follow.is_none() {
+                self.camera_pos = self.model.rules.normalize_pos(
+                    self.camera_pos + direction * Self::CAMERA_FOV * delta_time as f32,
+                );
             }
-            action.shoot = self
-                .geng
-                .window()
-                .is_button_pressed(geng::MouseButton::Left);
+            let mut action = &mut self.action;
+            action.target_vel = direction;
+            action.shoot = !typing
+                && self
+                    .geng
+                    .window()
+                    .is_button_pressed(geng::MouseButton::Left);
             action.aim = self.mouse_pos;
         }
+        self.next_input -= delta_time;
+        if self.next_input <= 0.0 {
+            self.next_input += 1.0 / Self::INPUT_RATE;
+            self.next_input = self.next_input.max(0.0);
+            let free_camera = self.spectating() && self.follow.is_none();
+            if let Source::Server(connection) = &mut self.source {
+                if free_camera {
+                    connection.send(ClientMessage::Look(self.camera_pos));
+                }
+                self.input_seq += 1;
+                connection.send(ClientMessage::Action {
+                    action: self.action.clone(),
+                    seq: self.input_seq,
+                    view_time: self.model.view_time(),
+                    ack: self.delta.ack(),
+                });
+                self.ping_watch.sent(self.input_seq);
+                self.model
+                    .prediction
+                    .input(self.input_seq, self.action.clone());
+            }
+        }
     }
     fn draw(&mut self, framebuffer: &mut ugli::Framebuffer) {
+        if let Source::Rejected(reason) = &self.source {
+            ugli::clear(framebuffer, Some(Color::BLACK), None);
+            let framebuffer_size = framebuffer.size().map(|x| x as f32);
+            let mid = framebuffer_size / 2.0;
+            self.font.draw_aligned(
+                framebuffer,
+                "Can not play on the server",
+                vec2(mid.x, mid.y + 48.0),
+                0.5,
+                32.0,
+                Color::rgb(1.0, 0.5, 0.5),
+            );
+            for (i, line) in reason.lines().enumerate() {
+                self.font.draw_aligned(
+                    framebuffer,
+                    line,
+                    vec2(mid.x, mid.y - i as f32 * 24.0),
+                    0.5,
+                    20.0,
+                    Color::rgb(1.0, 1.0, 1.0),
+                );
+            }
+            return;
+        }
         let rules = &self.model.rules;
-        let mut player_alive = false;
-        if let Some(id) = self.client_player_id {
+        if let Some(id) = self.camera_target() {
             if let Some(player) = self.model.players.get(&id) {
                 self.camera_pos = player.pos;
-                player_alive = true;
             }
         }
-        let player_alive = player_alive;
+        let spectating = self.spectating();
+        let player_alive = !spectating
+            && self
+                .client_player_id
+                .is_some_and(|id| self.model.players.contains_key(&id));
+        let replay = match &self.source {
+            Source::Replay(replay) => Some(replay),
+            _ => None,
+        };
 
         ugli::clear(framebuffer, Some(Color::BLACK), None);
         let framebuffer_size = framebuffer.size().map(|x| x as f32);
@@ -506,9 +1020,93 @@ impl geng::State for ClientApp {
                     );
                 }
             }
+            let mut spectators: Vec<&str> = self
+                .model
+                .spectators
+                .iter()
+                .filter_map(|id| self.player_names.get(id))
+                .map(|(name, _)| name.as_str())
+                .collect();
+            if !spectators.is_empty() {
+                spectators.sort_unstable();
+                y -= FONT_SIZE;
+                for line in std::iter::once("Spectators:").chain(spectators) {
+                    y -= FONT_SIZE;
+                    font.draw_aligned(
+                        framebuffer,
+                        line,
+                        vec2(framebuffer_size.x - 100.0, y),
+                        1.0,
+                        FONT_SIZE,
+                        Color::rgba(1.0, 1.0, 1.0, 0.4),
+                    );
+                }
+            }
         }
 
-        if !player_alive {
+        if let Some(replay) = replay {
+            fn format_time(time: f64) -> String {
+                let time = time as u64;
+                format!("{}:{:02}", time / 60, time % 60)
+            }
+            let mut status = format!(
+                "{} / {}  x{}",
+                format_time(replay.time),
+                format_time(replay.duration()),
+                replay.speed,
+            );
+            if replay.paused {
+                status.push_str("  paused");
+            }
+            if let Some((name, _)) = self.follow.and_then(|id| self.player_names.get(&id)) {
+                status = format!("{}  following {}", status, name);
+            }
+            font.draw_aligned(
+                framebuffer,
+                &status,
+                vec2(mid.x, 60.0),
+                0.5,
+                24.0,
+                Color::rgb(1.0, 1.0, 1.0),
+            );
+            font.draw_aligned(
+                framebuffer,
+                "Space to pause, Left/Right to seek, Up/Down to change speed",
+                vec2(mid.x, 36.0),
+                0.5,
+                16.0,
+                Color::rgb(0.5, 0.5, 0.5),
+            );
+            font.draw_aligned(
+                framebuffer,
+                "Q/E to follow players, C for free camera",
+                vec2(mid.x, 16.0),
+                0.5,
+                16.0,
+                Color::rgb(0.5, 0.5, 0.5),
+            );
+        } else if spectating {
+            let status = match self.follow.and_then(|id| self.player_names.get(&id)) {
+                Some((name, _)) => format!("Spectating {}", name),
+                None => "Spectating".to_owned(),
+            };
+            font.draw_aligned(
+                framebuffer,
+                &status,
+                vec2(mid.x, 60.0),
+                0.5,
+                24.0,
+                Color::rgb(1.0, 1.0, 1.0),
+            );
+            font.draw_aligned(
+                framebuffer,
+                "Q/E to follow players, C for free camera, R to spawn",
+                vec2(mid.x, 16.0),
+                0.5,
+                16.0,
+                Color::rgb(0.5, 0.5, 0.5),
+            );
+        } else if !player_alive {
             font.draw_aligned(
                 framebuffer,
                 "WASD to move",
@@ -527,7 +1125,7 @@ impl geng::State for ClientApp {
             );
             font.draw_aligned(
                 framebuffer,
-                "F to toggle fullscreen",
+                "F to toggle fullscreen, L to list rooms, Enter to chat",
                 vec2(0.0, 2.0 * scale) + mid,
                 0.5,
                 scale,
@@ -535,7 +1133,7 @@ impl geng::State for ClientApp {
             );
             font.draw_aligned(
                 framebuffer,
-                "Press R to spawn",
+                "Press R to spawn, V to spectate",
                 vec2(0.0, -4.0 * scale) + mid,
                 0.5,
                 scale * 2.0,
@@ -543,6 +1141,64 @@ impl geng::State for ClientApp {
             );
         }
 
+        if let Some(position) = self.queue_position {
+            font.draw_aligned(
+                framebuffer,
+                &format!("Server is full, you are #{} in the queue", position),
+                vec2(mid.x, framebuffer_size.y - 80.0),
+                0.5,
+                24.0,
+                Color::rgb(1.0, 1.0, 0.5),
+            );
+        }
+
+        if let Source::Reconnecting { .. } = self.source {
+            font.draw_aligned(
+                framebuffer,
+                "Connection lost, reconnecting...",
+                vec2(mid.x, framebuffer_size.y - 48.0),
+                0.5,
+                24.0,
+                Color::rgb(1.0, 0.5, 0.5),
+            );
+        }
+
+        self.chat.draw(font, framebuffer);
+
+        if let Some(rooms) = &self.room_list {
+            let mut y = mid.y + 100.0;
+            font.draw_aligned(
+                framebuffer,
+                "Rooms: 1-9 to join, L to close",
+                vec2(mid.x, y),
+                0.5,
+                24.0,
+                Color::rgb(1.0, 1.0, 1.0),
+            );
+            for (index, room) in rooms.iter().enumerate().take(9) {
+                y -= 24.0;
+                let here = if self.room.as_ref() == Some(&room.id) {
+                    " (you are here)"
+                } else {
+                    ""
+                };
+                font.draw_aligned(
+                    framebuffer,
+                    &format!(
+                        "{}. {}: {} players{}",
+                        index + 1,
+                        room.id,
+                        room.players,
+                        here
+                    ),
+                    vec2(mid.x, y),
+                    0.5,
+                    20.0,
+                    Color::rgb(0.8, 0.8, 0.8),
+                );
+            }
+        }
+
         font.draw(
             framebuffer,
             &self.ping_watch.text,
@@ -561,23 +1217,85 @@ impl geng::State for ClientApp {
         {
             return;
         }
-        match event {
-            geng::Event::KeyDown { key } => match key {
-                geng::Key::R => {
-                    self.connection.send(ClientMessage::Spawn);
-                    if self.music.is_none() {
-                        self.music = Some({
-                            let mut music = self.assets.music.play();
-                            music.set_volume(0.2);
-                            music
-                        })
+        let key = match event {
+            geng::Event::KeyDown { key } => key,
+            _ => return,
+        };
+        if self.chat.input.is_some() {
+            let window = self.geng.window();
+            let shift = window.is_key_pressed(geng::Key::LShift)
+                || window.is_key_pressed(geng::Key::RShift);
+            if let Some(text) = self.chat.handle_key(key, shift) {
+                let names = self
+                    .player_names
+                    .iter()
+                    .map(|(id, (name, _))| (*id, name.as_str()));
+                let message = match chat::host_command(&text, names) {
+                    Some(Ok(message)) => message,
+                    Some(Err(e)) => {
+                        self.chat.notice(&e);
+                        return;
                     }
+                    None => ClientMessage::Chat(text),
+                };
+                if let Source::Server(connection) = &mut self.source {
+                    connection.send(message);
                 }
-                geng::Key::F => {
-                    self.geng.window().toggle_fullscreen();
+            }
+            return;
+        }
+        if self.spectating() {
+            self.handle_spectator_key(key);
+        }
+        if let Source::Replay(_) = self.source {
+            self.handle_replay_key(key);
+            return;
+        }
+        match key {
+            geng::Key::R => {
+                if let Source::Server(connection) = &mut self.source {
+                    connection.send(ClientMessage::Spawn);
                 }
-                _ => {}
-            },
+                self.set_follow(None);
+                if self.music.is_none() {
+                    self.music = Some({
+                        let mut music = self.assets.music.play();
+                        music.set_volume(0.2);
+                        music
+                    })
+                }
+            }
+            geng::Key::V => {
+                if let Source::Server(connection) = &mut self.source {
+                    connection.send(ClientMessage::Spectate);
+                }
+            }
+            geng::Key::F => {
+                self.geng.window().toggle_fullscreen();
+            }
+            geng::Key::Enter => {
+                if let Source::Server(_) = self.source {
+                    self.chat.input = Some(String::new());
+                }
+            }
+            geng::Key::L => {
+                if self.room_list.take().is_some() {
+                    return;
+                }
+                if let Source::Server(connection) = &mut self.source {
+                    connection.send(ClientMessage::ListRooms);
+                    self.room_list = Some(Vec::new());
+                }
+            }
+            geng::Key::Num1 => self.join_room(0),
+            geng::Key::Num2 => self.join_room(1),
+            geng::Key::Num3 => self.join_room(2),
+            geng::Key::Num4 => self.join_room(3),
+            geng::Key::Num5 => self.join_room(4),
+            geng::Key::Num6 => self.join_room(5),
+            geng::Key::Num7 => self.join_room(6),
+            geng::Key::Num8 => self.join_room(7),
+            geng::Key::Num9 => self.join_room(8),
             _ => {}
         }
     }
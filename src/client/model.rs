@@ -26,6 +26,14 @@ impl Entity {
     fn mass(&self) -> f32 {
         self.size * self.size
     }
+    /// Skips the delayed extrapolation, for the locally predicted player
+    fn predict(&mut self, pos: Vec2<f32>, vel: Vec2<f32>) {
+        self.pos = pos;
+        self.next_pos = pos;
+        self.vel = vel;
+        self.next_vel = vel;
+        self.delayed = 0.0;
+    }
     fn recv(
         &mut self,
         e: common_model::Entity,
@@ -224,15 +232,23 @@ impl Player {
             }
         }
     }
-    fn update(&mut self, delta_time: f32, rules: &Rules) {
+    fn update(
+        &mut self,
+        delta_time: f32,
+        rules: &Rules,
+        predicted: Option<(Vec2<f32>, Vec2<f32>)>,
+    ) {
         self.time += delta_time;
+        match predicted {
+            Some((pos, vel)) => self.entity.predict(pos, vel),
+            None => self.entity.update(delta_time, rules),
+        }
         if let Some((projectile, _)) = &mut self.projectile {
             projectile.pos = self.entity.pos
                 + rules
                     .normalize_delta(self.action.aim - self.entity.pos)
                     .clamp(self.entity.size);
         }
-        self.entity.update(delta_time, rules);
     }
     pub fn draw(&self, client_player_id: Option<Id>, renderer: &mut CircleRenderer) {
         renderer.queue(circle_renderer::Instance {
@@ -274,6 +290,7 @@ pub struct Model {
     assets: Rc<Assets>,
     sound_player: Rc<SoundPlayer>,
     pub scores: HashMap<Id, common_model::Scores>,
+    pub spectators: HashSet<Id>,
     pub last_sync_time: Option<f32>,
     pub client_player_id: Option<Id>,
     pub rules: Rules,
@@ -281,6 +298,7 @@ pub struct Model {
     pub projectiles: HashMap<Id, Projectile>,
     pub food: HashMap<Id, common_model::Food>,
     pub sparks: Vec<Spark>,
+    pub prediction: Prediction,
 }
 
 impl Model {
@@ -288,6 +306,7 @@ impl Model {
         Self {
             assets: assets.clone(),
             scores: HashMap::new(),
+            spectators: HashSet::new(),
             sound_player: sound_player.clone(),
             last_sync_time: None,
             rules: default(),
@@ -296,12 +315,16 @@ impl Model {
             food: HashMap::new(),
             sparks: Vec::new(),
             client_player_id: None,
+            prediction: Prediction::new(),
         }
     }
     pub fn update(&mut self, delta_time: f32) {
         let rules = &self.rules;
+        self.prediction.update(delta_time, rules);
+        let predicted = self.prediction.state(rules);
         for player in self.players.values_mut() {
-            player.update(delta_time, rules);
+            let local = Some(player.id) == self.client_player_id;
+            player.update(delta_time, rules, predicted.filter(|_| local));
             if let Some((projectile, _)) = &mut player.projectile {
                 projectile.update_sparks(self.client_player_id, delta_time, &mut self.sparks);
             }
@@ -315,52 +338,60 @@ impl Model {
         }
         self.sparks.retain(|e| e.alive());
     }
-    pub fn recv(&mut self, mut message: ServerMessage) {
-        self.client_player_id = Some(message.client_player_id);
-        self.rules = message.model.rules;
+    /// Server time of what is on screen, entities are shown `Entity::DELAY` behind the latest state.
+    ///
+    /// Never further back than the server is able to rewind.
+    pub fn view_time(&self) -> Option<f32> {
+        let delay = Entity::DELAY.min(common_model::Model::MAX_REWIND);
+        self.last_sync_time.map(|time| time - delay)
+    }
+    /// Inputs up to `last_input` are applied in the given state, later ones are predicted
+    pub fn recv_model(&mut self, mut model: common_model::ModelMessage, last_input: Option<u64>) {
+        self.rules = model.rules;
         let rules = &self.rules;
+        if let (Some(id), Some(seq)) = (self.client_player_id, last_input) {
+            self.prediction
+                .reconcile(model.players.get(&id).cloned(), seq, rules);
+        }
         let sync_delay = if let Some(time) = self.last_sync_time {
-            (message.model.current_time - time) / 2.0
+            (model.current_time - time) / 2.0
         } else {
             0.0
         };
-        self.last_sync_time = Some(message.model.current_time);
+        self.last_sync_time = Some(model.current_time);
 
-        let mut dead_players: HashSet<Id> = self.players.keys().cloned().collect();
+        // Entities missing from the update are either dead or out of view,
+        // actual deaths are told by events
+        let alive = &model.players;
+        self.players.retain(|id, _| alive.contains_key(id));
         for player in self.players.values_mut() {
-            if let Some(upd) = message.model.players.remove(&player.id) {
-                dead_players.remove(&player.id);
+            if let Some(upd) = model.players.remove(&player.id) {
                 player.recv(upd, sync_delay, rules);
+                if Some(player.id) == self.client_player_id {
+                    if let Some(action) = self.prediction.action() {
+                        player.action = action.clone();
+                    }
+                }
             }
         }
-        for player in dead_players {
-            if let Some(player) = self.players.remove(&player) {
-                self.sound_player.play(&self.assets.death_sound, player.pos);
-            }
-        }
-        for (id, p) in message.model.players {
+        for (id, p) in model.players {
             self.players
                 .insert(id, Player::new(p, &self.sound_player, &self.assets));
         }
 
-        let mut dead_projectiles: HashSet<Id> = self.projectiles.keys().cloned().collect();
+        let alive = &model.projectiles;
+        self.projectiles.retain(|id, _| alive.contains_key(id));
         for projectile in self.projectiles.values_mut() {
-            if let Some(upd) = message.model.projectiles.remove(&projectile.id) {
-                dead_projectiles.remove(&projectile.id);
+            if let Some(upd) = model.projectiles.remove(&projectile.id) {
                 projectile.recv(upd, sync_delay, rules);
             }
         }
-        for projectile in dead_projectiles {
-            if let Some(p) = self.projectiles.remove(&projectile) {
-                self.sound_player.play(&self.assets.hit_sound, p.pos); // TODO: on actual hit
-            }
-        }
-        for (id, p) in message.model.projectiles {
-            self.sound_player.play(&self.assets.shoot_sound, p.pos);
+        for (id, p) in model.projectiles {
             self.projectiles.insert(id, Projectile::new(p));
         }
-
-        for event in message.events {
+    }
+    pub fn recv_events(&mut self, events: Vec<common_model::Event>) {
+        for event in events {
             match event {
                 common_model::Event::Food(event) => match event {
                     common_model::FoodEvent::Add(food) => {
@@ -371,11 +402,27 @@ impl Model {
                             self.sound_player.play(&self.assets.heal_sound, food.pos);
                         }
                     }
+                    common_model::FoodEvent::OutOfView(id) => {
+                        self.food.remove(&id);
+                    }
                 },
-                common_model::Event::PlayerName { .. } => {}
+                common_model::Event::PlayerName { .. } | common_model::Event::Chat { .. } => {}
                 common_model::Event::ScoresUpdate(scores) => {
                     self.scores = scores;
                 }
+                common_model::Event::SpectatorsUpdate(spectators) => {
+                    self.spectators = spectators;
+                }
+                common_model::Event::Shot { pos } => {
+                    self.sound_player.play(&self.assets.shoot_sound, pos);
+                }
+                common_model::Event::PlayerDied { pos, .. } => {
+                    self.sound_player.play(&self.assets.death_sound, pos);
+                }
+                common_model::Event::ProjectileDestroyed { pos } => {
+                    // TODO: on actual hit
+                    self.sound_player.play(&self.assets.hit_sound, pos);
+                }
             }
         }
     }
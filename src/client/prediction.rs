@@ -0,0 +1,89 @@
+use super::*;
+
+/// Local player simulated ahead of the server with the inputs it has not applied yet
+pub struct Prediction {
+    /// Inputs sent but not yet applied by the server, with how long each of them was held
+    pending: VecDeque<(u64, Action, f32)>,
+    player: Option<common_model::Player>,
+    /// Jump made by the last reconciliation, smoothed out over time instead of snapping
+    error: Vec2<f32>,
+}
+
+impl Prediction {
+    /// Fraction of the error left after a second
+    const ERROR_DECAY: f32 = 0.001;
+
+    pub fn new() -> Self {
+        Self {
+            pending: VecDeque::new(),
+            player: None,
+            error: vec2(0.0, 0.0),
+        }
+    }
+
+    /// Latest action sent to the server
+    pub fn action(&self) -> Option<&Action> {
+        self.pending.back().map(|(_, action, _)| action)
+    }
+
+    pub fn input(&mut self, seq: u64, action: Action) {
+        self.pending.push_back((seq, action, 0.0));
+    }
+
+    /// Advances the local player holding the latest input
+    pub fn update(&mut self, delta_time: f32, rules: &Rules) {
+        self.error *= Self::ERROR_DECAY.powf(delta_time);
+        let (action, held) = match self.pending.back_mut() {
+            Some((_, action, held)) => (action, held),
+            None => return,
+        };
+        *held += delta_time;
+        if let Some(player) = &mut self.player {
+            player.action = action.clone();
+            player.update_movement(delta_time, rules);
+        }
+    }
+
+    /// Position and velocity the local player should be drawn with
+    pub fn state(&self, rules: &Rules) -> Option<(Vec2<f32>, Vec2<f32>)> {
+        self.player
+            .as_ref()
+            .map(|player| (rules.normalize_pos(player.pos + self.error), player.vel))
+    }
+
+    /// Starts over from the server state, replaying the inputs it has not applied yet
+    pub fn reconcile(
+        &mut self,
+        player: Option<common_model::Player>,
+        last_input: u64,
+        rules: &Rules,
+    ) {
+        while let Some(&(seq, _, _)) = self.pending.front() {
+            if seq > last_input {
+                break;
+            }
+            self.pending.pop_front();
+        }
+        let mut player = match player {
+            Some(player) => player,
+            None => {
+                self.player = None;
+                self.error = vec2(0.0, 0.0);
+                return;
+            }
+        };
+        let tick = (1.0 / common_model::Model::TICKS_PER_SECOND) as f32;
+        for (_, action, held) in &self.pending {
+            player.action = action.clone();
+            let mut time = *held;
+            while time > 0.0 {
+                player.update_movement(time.min(tick), rules);
+                time -= tick;
+            }
+        }
+        if let Some((pos, _)) = self.state(rules) {
+            self.error = rules.normalize_delta(pos - player.pos);
+        }
+        self.player = Some(player);
+    }
+}
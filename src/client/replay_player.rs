@@ -0,0 +1,145 @@
+use super::*;
+
+use common_model::{EncodedUpdate, Event};
+
+struct Frame {
+    tick: u64,
+    keyframe: bool,
+    update: EncodedUpdate,
+    state: Vec<Event>,
+    events: Vec<Event>,
+}
+
+/// Plays back a recorded match, producing the messages server has sent at the time
+pub struct ReplayPlayer {
+    pub header: ReplayHeader,
+    frames: Vec<Frame>,
+    next_frame: usize,
+    /// Client state was reset, so the next frame has to bring back food, names and scores
+    reset: bool,
+    /// Id used for the viewer when not following anyone
+    nobody: Id,
+    /// Playback position, in seconds since start of the recording
+    pub time: f64,
+    pub paused: bool,
+    pub speed: f64,
+}
+
+impl ReplayPlayer {
+    pub const MIN_SPEED: f64 = 0.25;
+    pub const MAX_SPEED: f64 = 8.0;
+
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let mut reader = std::io::BufReader::new(std::fs::File::open(path)?);
+        let mut header = String::new();
+        reader.read_line(&mut header)?;
+        let header: ReplayHeader = serde_json::from_str(&header).context("Bad replay header")?;
+        if header.version != ReplayHeader::VERSION {
+            anyhow::bail!(
+                "Replay was recorded by build {} with format version {}, expected version {}",
+                header.build,
+                header.version,
+                ReplayHeader::VERSION,
+            );
+        }
+        let mut frames = Vec::new();
+        loop {
+            match bincode::deserialize_from(&mut reader) {
+                // Inputs are there to review what players did, playback only needs the frames
+                Ok(ReplayEntry::Input { .. }) => {}
+                Ok(ReplayEntry::Frame {
+                    tick,
+                    keyframe,
+                    update,
+                    state,
+                    events,
+                }) => frames.push(Frame {
+                    tick,
+                    keyframe,
+                    update,
+                    state,
+                    events,
+                }),
+                Err(e) => {
+                    if let bincode::ErrorKind::Io(e) = &*e {
+                        if e.kind() == std::io::ErrorKind::UnexpectedEof {
+                            break;
+                        }
+                    }
+                    warn!("Replay is truncated: {}", e);
+                    break;
+                }
+            }
+        }
+        if frames.is_empty() {
+            anyhow::bail!("Replay has no frames");
+        }
+        info!("Loaded replay of {} frames", frames.len());
+        Ok(Self {
+            header,
+            frames,
+            next_frame: 0,
+            reset: true,
+            nobody: Id::new(),
+            time: 0.0,
+            paused: false,
+            speed: 1.0,
+        })
+    }
+
+    fn frame_time(&self, frame: &Frame) -> f64 {
+        (frame.tick - self.frames[0].tick) as f64 / common_model::Model::TICKS_PER_SECOND
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.frame_time(self.frames.last().unwrap())
+    }
+
+    pub fn update(&mut self, delta_time: f64) {
+        if !self.paused {
+            self.time = (self.time + delta_time * self.speed).min(self.duration());
+        }
+    }
+
+    /// Moves playback position, returning whether client state has to be reset
+    pub fn seek(&mut self, time: f64) -> bool {
+        self.time = clamp(time, 0.0..=self.duration());
+        if self.next_frame > 0 && self.frame_time(&self.frames[self.next_frame - 1]) > self.time {
+            // Going back starts from the last keyframe before the new position
+            self.next_frame = (0..self.next_frame)
+                .rev()
+                .find(|&index| {
+                    let frame = &self.frames[index];
+                    frame.keyframe && self.frame_time(frame) <= self.time
+                })
+                .unwrap_or(0);
+            self.reset = true;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Messages for frames played since last call, as seen by the `follow`ed player
+    pub fn new_messages(&mut self, follow: Option<Id>) -> Vec<StateUpdate> {
+        let mut result = Vec::new();
+        while let Some(frame) = self.frames.get(self.next_frame) {
+            if self.frame_time(frame) > self.time {
+                break;
+            }
+            let mut events = Vec::new();
+            if std::mem::take(&mut self.reset) {
+                events.extend(frame.state.iter().cloned());
+            }
+            events.extend(frame.events.iter().cloned());
+            result.push(StateUpdate {
+                model: frame.update.clone(),
+                events,
+                client_player_id: follow.unwrap_or(self.nobody),
+                last_input: None,
+            });
+            self.next_frame += 1;
+        }
+        result
+    }
+}
@@ -0,0 +1,397 @@
+use super::*;
+
+/// Part of the world that bots base their decisions on
+pub struct BotView<'a> {
+    pub rules: &'a Rules,
+    pub current_time: f32,
+    pub players: &'a HashMap<Id, Player>,
+    pub projectiles: &'a HashMap<Id, Projectile>,
+    pub food: &'a [Food],
+}
+
+impl<'a> BotView<'a> {
+    fn distance(&self, a: Vec2<f32>, b: Vec2<f32>) -> f32 {
+        self.rules.normalize_delta(a - b).len()
+    }
+    fn closest_food(&self, pos: Vec2<f32>) -> Option<&'a Food> {
+        self.food.iter().min_by(|a, b| {
+            self.distance(a.pos, pos)
+                .partial_cmp(&self.distance(b.pos, pos))
+                .unwrap()
+        })
+    }
+    fn closest_enemy(&self, me: &Player) -> Option<&'a Player> {
+        self.players
+            .values()
+            .filter(|player| player.id != me.id)
+            .min_by(|a, b| {
+                self.distance(a.pos, me.pos)
+                    .partial_cmp(&self.distance(b.pos, me.pos))
+                    .unwrap()
+            })
+    }
+}
+
+/// Decision making of a single bot, keeping whatever state it needs between ticks
+pub trait BotBrain: Send {
+    fn think(&mut self, view: &BotView, me: &Player) -> Action;
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BrainKind {
+    /// Farms the closest food, shooting the closest enemy in range
+    Standard,
+    /// Hunts down enemies, eating only when too small to fight
+    Aggressive,
+    /// Farms food and runs away from enemies, shooting only in self defense
+    Farmer,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct BotPersonality {
+    pub name: String,
+    pub brain: BrainKind,
+    /// Enemies closer than this are shot at
+    pub shoot_range: f32,
+    /// Bot will not shoot being smaller than this
+    pub min_size_to_shoot: f32,
+    /// Projectile is released once it gets this big
+    pub max_charge: f32,
+    /// Time between decisions, in seconds
+    pub reaction_time: f32,
+    /// How much to lead moving targets, from 0 (aim at where they are) to 1 (full prediction)
+    #[serde(default)]
+    pub aim_lead: f32,
+    /// Time it takes to notice an incoming projectile, never dodging if not set
+    #[serde(default)]
+    pub dodge_reaction_time: Option<f32>,
+}
+
+impl BotPersonality {
+    pub const DEFAULT: &'static str = "normal";
+
+    pub fn builtin() -> Vec<Self> {
+        let normal = Self {
+            name: "normal".to_owned(),
+            brain: BrainKind::Standard,
+            shoot_range: 17.0,
+            min_size_to_shoot: 0.7,
+            max_charge: 0.4,
+            reaction_time: 0.0,
+            aim_lead: 0.5,
+            dodge_reaction_time: Some(0.4),
+        };
+        vec![
+            Self {
+                name: "easy".to_owned(),
+                shoot_range: 12.0,
+                min_size_to_shoot: 0.9,
+                max_charge: 0.25,
+                reaction_time: 0.5,
+                aim_lead: 0.0,
+                dodge_reaction_time: None,
+                ..normal.clone()
+            },
+            Self {
+                name: "hard".to_owned(),
+                shoot_range: 22.0,
+                min_size_to_shoot: 0.6,
+                max_charge: 0.5,
+                aim_lead: 1.0,
+                dodge_reaction_time: Some(0.15),
+                ..normal.clone()
+            },
+            Self {
+                name: "aggressive".to_owned(),
+                brain: BrainKind::Aggressive,
+                shoot_range: 25.0,
+                min_size_to_shoot: 0.5,
+                max_charge: 0.5,
+                reaction_time: 0.1,
+                aim_lead: 0.8,
+                dodge_reaction_time: Some(0.3),
+            },
+            Self {
+                name: "farmer".to_owned(),
+                brain: BrainKind::Farmer,
+                shoot_range: 8.0,
+                min_size_to_shoot: 1.2,
+                max_charge: 0.3,
+                reaction_time: 0.2,
+                aim_lead: 0.3,
+                dodge_reaction_time: Some(0.2),
+            },
+            normal,
+        ]
+    }
+
+    /// Builtin personalities, with ones from `overrides` replacing those of the same name
+    pub fn merge(overrides: Vec<Self>) -> Vec<Self> {
+        let mut result = Self::builtin();
+        for personality in overrides {
+            result.retain(|p| p.name != personality.name);
+            result.push(personality);
+        }
+        result
+    }
+
+    pub fn find<'a>(personalities: &'a [Self], name: &str) -> Option<&'a Self> {
+        personalities.iter().find(|p| p.name == name)
+    }
+
+    pub fn brain(&self) -> Box<dyn BotBrain> {
+        let inner: Box<dyn BotBrain> = match self.brain {
+            BrainKind::Standard => Box::new(StandardBrain {
+                personality: self.clone(),
+            }),
+            BrainKind::Aggressive => Box::new(AggressiveBrain {
+                personality: self.clone(),
+                target: None,
+            }),
+            BrainKind::Farmer => Box::new(FarmerBrain {
+                personality: self.clone(),
+            }),
+        };
+        let reacting = Box::new(Reacting {
+            inner,
+            reaction_time: self.reaction_time,
+            next_think: 0.0,
+            action: None,
+        });
+        match self.dodge_reaction_time {
+            Some(reaction_time) => Box::new(Dodging {
+                inner: reacting,
+                reaction_time,
+                noticed: HashMap::new(),
+            }),
+            None => reacting,
+        }
+    }
+
+    /// Where to aim to hit the target, given how good this bot is at leading
+    fn aim(&self, view: &BotView, me: &Player, target: &Player) -> Vec2<f32> {
+        let speed = Projectile::speed(self.max_charge);
+        match intercept(view.rules, me.pos, target.pos, target.vel, speed) {
+            Some(pos) => target.pos + view.rules.normalize_delta(pos - target.pos) * self.aim_lead,
+            None => target.pos,
+        }
+    }
+
+    fn should_shoot(&self, me: &Player) -> bool {
+        if me.size < self.min_size_to_shoot {
+            return false;
+        }
+        if let Some(p) = &me.projectile {
+            if p.size > self.max_charge {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn action(&self, me: &Player, target_vel: Vec2<f32>, shoot: Option<Vec2<f32>>) -> Action {
+        let shoot = shoot.filter(|_| self.should_shoot(me));
+        Action {
+            target_vel,
+            shoot: shoot.is_some(),
+            aim: shoot
+                .or(me.projectile.as_ref().map(|p| p.pos))
+                .unwrap_or(vec2(0.0, 0.0)),
+        }
+    }
+}
+
+/// Repeats the last decision of the inner brain until its reaction time passes
+struct Reacting {
+    inner: Box<dyn BotBrain>,
+    reaction_time: f32,
+    next_think: f32,
+    action: Option<Action>,
+}
+
+impl BotBrain for Reacting {
+    fn think(&mut self, view: &BotView, me: &Player) -> Action {
+        if let Some(action) = &self.action {
+            if view.current_time < self.next_think {
+                return action.clone();
+            }
+        }
+        self.next_think = view.current_time + self.reaction_time;
+        let action = self.inner.think(view, me);
+        self.action = Some(action.clone());
+        action
+    }
+}
+
+/// Steers sideways from enemy projectiles that are on a collision course
+struct Dodging {
+    inner: Box<dyn BotBrain>,
+    reaction_time: f32,
+    /// When each of the flying projectiles was first seen
+    noticed: HashMap<Id, f32>,
+}
+
+impl Dodging {
+    /// How far ahead projectile paths are checked, in seconds
+    const HORIZON: f32 = 1.5;
+    const MARGIN: f32 = 0.5;
+}
+
+impl BotBrain for Dodging {
+    fn think(&mut self, view: &BotView, me: &Player) -> Action {
+        let mut action = self.inner.think(view, me);
+        self.noticed
+            .retain(|id, _| view.projectiles.contains_key(id));
+        let mut closest_threat: Option<(f32, Vec2<f32>)> = None;
+        for projectile in view.projectiles.values() {
+            if projectile.owner_id == me.id {
+                continue;
+            }
+            let noticed = *self
+                .noticed
+                .entry(projectile.id)
+                .or_insert(view.current_time);
+            if view.current_time - noticed < self.reaction_time {
+                continue;
+            }
+            let delta = view.rules.normalize_delta(me.pos - projectile.pos);
+            let vel = projectile.vel - me.vel;
+            if Vec2::dot(vel, vel) < 1e-6 {
+                continue;
+            }
+            let t = Vec2::dot(delta, vel) / Vec2::dot(vel, vel);
+            if !(0.0..=Self::HORIZON).contains(&t) {
+                continue;
+            }
+            let miss = delta - vel * t;
+            if miss.len() > me.size + projectile.size + Self::MARGIN {
+                continue;
+            }
+            if closest_threat.is_none_or(|(threat_t, _)| t < threat_t) {
+                let away = if miss.len() > 1e-3 {
+                    miss.normalize()
+                } else {
+                    vel.rotate_90().normalize()
+                };
+                closest_threat = Some((t, away));
+            }
+        }
+        if let Some((_, away)) = closest_threat {
+            action.target_vel = away;
+        }
+        action
+    }
+}
+
+/// Point to aim at to hit a target moving with constant velocity by a projectile of given speed
+pub fn intercept(
+    rules: &Rules,
+    from: Vec2<f32>,
+    target_pos: Vec2<f32>,
+    target_vel: Vec2<f32>,
+    speed: f32,
+) -> Option<Vec2<f32>> {
+    let delta = rules.normalize_delta(target_pos - from);
+    let a = Vec2::dot(target_vel, target_vel) - speed * speed;
+    let b = 2.0 * Vec2::dot(delta, target_vel);
+    let c = Vec2::dot(delta, delta);
+    let t = if a.abs() < 1e-6 {
+        if b.abs() < 1e-6 {
+            return None;
+        }
+        -c / b
+    } else {
+        let d = b * b - 4.0 * a * c;
+        if d < 0.0 {
+            return None;
+        }
+        let t1 = (-b - d.sqrt()) / (2.0 * a);
+        let t2 = (-b + d.sqrt()) / (2.0 * a);
+        let (t1, t2) = partial_min_max(t1, t2);
+        if t1 > 0.0 {
+            t1
+        } else {
+            t2
+        }
+    };
+    if t <= 0.0 {
+        return None;
+    }
+    Some(rules.normalize_pos(from + delta + target_vel * t))
+}
+
+struct StandardBrain {
+    personality: BotPersonality,
+}
+
+impl BotBrain for StandardBrain {
+    fn think(&mut self, view: &BotView, me: &Player) -> Action {
+        let mut shoot = None;
+        if let Some(e) = view.closest_enemy(me) {
+            if view.distance(e.pos, me.pos) < self.personality.shoot_range {
+                shoot = Some(self.personality.aim(view, me, e));
+            }
+        }
+        let target_vel = view
+            .closest_food(me.pos)
+            .map(|f| view.rules.normalize_delta(f.pos - me.pos))
+            .unwrap_or(vec2(0.0, 0.0));
+        self.personality.action(me, target_vel, shoot)
+    }
+}
+
+struct AggressiveBrain {
+    personality: BotPersonality,
+    target: Option<Id>,
+}
+
+impl BotBrain for AggressiveBrain {
+    fn think(&mut self, view: &BotView, me: &Player) -> Action {
+        if !self.target.is_some_and(|id| view.players.contains_key(&id)) {
+            self.target = view.closest_enemy(me).map(|e| e.id);
+        }
+        let target = match self.target.and_then(|id| view.players.get(&id)) {
+            Some(target) if me.size >= self.personality.min_size_to_shoot => target,
+            _ => {
+                let target_vel = view
+                    .closest_food(me.pos)
+                    .map(|f| view.rules.normalize_delta(f.pos - me.pos))
+                    .unwrap_or(vec2(0.0, 0.0));
+                return self.personality.action(me, target_vel, None);
+            }
+        };
+        let delta = view.rules.normalize_delta(target.pos - me.pos);
+        let shoot = if delta.len() < self.personality.shoot_range {
+            Some(self.personality.aim(view, me, target))
+        } else {
+            None
+        };
+        self.personality.action(me, delta, shoot)
+    }
+}
+
+struct FarmerBrain {
+    personality: BotPersonality,
+}
+
+impl BotBrain for FarmerBrain {
+    fn think(&mut self, view: &BotView, me: &Player) -> Action {
+        let mut target_vel = view
+            .closest_food(me.pos)
+            .map(|f| view.rules.normalize_delta(f.pos - me.pos).clamp(1.0))
+            .unwrap_or(vec2(0.0, 0.0));
+        let mut shoot = None;
+        if let Some(e) = view.closest_enemy(me) {
+            let delta = view.rules.normalize_delta(e.pos - me.pos);
+            if delta.len() > 1e-3 && delta.len() < self.personality.shoot_range * 2.0 {
+                target_vel -= delta.normalize() * 2.0;
+            }
+            if delta.len() < self.personality.shoot_range {
+                shoot = Some(self.personality.aim(view, me, e));
+            }
+        }
+        self.personality.action(me, target_vel, shoot)
+    }
+}
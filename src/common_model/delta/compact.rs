@@ -0,0 +1,634 @@
+use super::*;
+
+/// Velocities and sizes are quantized to 16 bits within these ranges.
+///
+/// Projectiles have no top speed, see `Projectile::speed`, so faster ones are sent in full.
+const VEL_RANGE: f32 = 128.0;
+const SIZE_RANGE: f32 = 128.0;
+const TARGET_VEL_RANGE: f32 = 2.0;
+
+struct Writer {
+    data: Vec<u8>,
+    world_size: f32,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+    fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+    fn i16(&mut self, value: i16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+    fn f32(&mut self, value: f32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.u8(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.u8(value as u8);
+    }
+    fn bytes(&mut self, data: &[u8]) {
+        self.varint(data.len() as u64);
+        self.data.extend_from_slice(data);
+    }
+    fn id(&mut self, id: Id) {
+        self.varint(id.0 as u64);
+    }
+    /// World wraps around, so the whole `u16` range is used for `0..world_size`
+    fn coord(&mut self, value: f32) {
+        let value = (value / self.world_size).rem_euclid(1.0);
+        self.u16(((value * 65536.0).round() as u32 & 0xffff) as u16);
+    }
+    fn pos(&mut self, pos: Vec2<f32>) {
+        self.coord(pos.x);
+        self.coord(pos.y);
+    }
+    /// Values out of range follow `i16::MIN` in full, quantizing never gives it
+    fn signed(&mut self, value: f32, range: f32) {
+        if value.abs() <= range {
+            self.i16((value / range * i16::MAX as f32).round() as i16);
+        } else {
+            self.i16(i16::MIN);
+            self.f32(value);
+        }
+    }
+    fn unsigned(&mut self, value: f32, range: f32) {
+        self.u16((clamp(value / range, 0.0..=1.0) * u16::MAX as f32).round() as u16);
+    }
+    fn vel(&mut self, vel: Vec2<f32>) {
+        self.signed(vel.x, VEL_RANGE);
+        self.signed(vel.y, VEL_RANGE);
+    }
+    fn entity(&mut self, entity: &Entity) {
+        self.id(entity.id);
+        self.pos(entity.pos);
+        self.vel(entity.vel);
+        self.unsigned(entity.size, SIZE_RANGE);
+    }
+    fn entity_delta(&mut self, delta: &EntityDelta) {
+        self.u8(delta.pos.is_some() as u8
+            | (delta.vel.is_some() as u8) << 1
+            | (delta.size.is_some() as u8) << 2);
+        if let Some(pos) = delta.pos {
+            self.pos(pos);
+        }
+        if let Some(vel) = delta.vel {
+            self.vel(vel);
+        }
+        if let Some(size) = delta.size {
+            self.unsigned(size, SIZE_RANGE);
+        }
+    }
+    fn action(&mut self, action: &Action) {
+        self.u8(action.shoot as u8);
+        self.signed(action.target_vel.x, TARGET_VEL_RANGE);
+        self.signed(action.target_vel.y, TARGET_VEL_RANGE);
+        self.pos(action.aim);
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    world_size: f32,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> anyhow::Result<&'a [u8]> {
+        if self.data.len() < len {
+            anyhow::bail!("Unexpected end of message");
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Ok(head)
+    }
+    fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+    fn u16(&mut self) -> anyhow::Result<u16> {
+        let data = self.take(2)?;
+        Ok(u16::from_le_bytes([data[0], data[1]]))
+    }
+    fn i16(&mut self) -> anyhow::Result<i16> {
+        let data = self.take(2)?;
+        Ok(i16::from_le_bytes([data[0], data[1]]))
+    }
+    fn f32(&mut self) -> anyhow::Result<f32> {
+        let data = self.take(4)?;
+        Ok(f32::from_le_bytes([data[0], data[1], data[2], data[3]]))
+    }
+    fn varint(&mut self) -> anyhow::Result<u64> {
+        let mut result = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift >= 64 {
+                anyhow::bail!("Varint is too long");
+            }
+            result |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(result);
+            }
+            shift += 7;
+        }
+    }
+    fn bytes(&mut self) -> anyhow::Result<&'a [u8]> {
+        let len = self.varint()? as usize;
+        self.take(len)
+    }
+    fn id(&mut self) -> anyhow::Result<Id> {
+        Ok(Id(self.varint()? as usize))
+    }
+    fn coord(&mut self) -> anyhow::Result<f32> {
+        Ok(self.u16()? as f32 / 65536.0 * self.world_size)
+    }
+    fn pos(&mut self) -> anyhow::Result<Vec2<f32>> {
+        Ok(vec2(self.coord()?, self.coord()?))
+    }
+    fn signed(&mut self, range: f32) -> anyhow::Result<f32> {
+        match self.i16()? {
+            i16::MIN => self.f32(),
+            value => Ok(value as f32 / i16::MAX as f32 * range),
+        }
+    }
+    fn unsigned(&mut self, range: f32) -> anyhow::Result<f32> {
+        Ok(self.u16()? as f32 / u16::MAX as f32 * range)
+    }
+    fn vel(&mut self) -> anyhow::Result<Vec2<f32>> {
+        Ok(vec2(self.signed(VEL_RANGE)?, self.signed(VEL_RANGE)?))
+    }
+    fn entity(&mut self) -> anyhow::Result<Entity> {
+        Ok(Entity {
+            id: self.id()?,
+            pos: self.pos()?,
+            vel: self.vel()?,
+            size: self.unsigned(SIZE_RANGE)?,
+        })
+    }
+    fn entity_delta(&mut self) -> anyhow::Result<EntityDelta> {
+        let flags = self.u8()?;
+        Ok(EntityDelta {
+            pos: if flags & 1 != 0 {
+                Some(self.pos()?)
+            } else {
+                None
+            },
+            vel: if flags & 2 != 0 {
+                Some(self.vel()?)
+            } else {
+                None
+            },
+            size: if flags & 4 != 0 {
+                Some(self.unsigned(SIZE_RANGE)?)
+            } else {
+                None
+            },
+        })
+    }
+    fn action(&mut self) -> anyhow::Result<Action> {
+        let shoot = self.u8()? != 0;
+        Ok(Action {
+            shoot,
+            target_vel: vec2(
+                self.signed(TARGET_VEL_RANGE)?,
+                self.signed(TARGET_VEL_RANGE)?,
+            ),
+            aim: self.pos()?,
+        })
+    }
+}
+
+/// Values replicated through `MapDelta` in compact form
+trait Compact: Diff {
+    fn id(&self) -> Id;
+    fn write(&self, writer: &mut Writer);
+    fn read(reader: &mut Reader) -> anyhow::Result<Self>;
+    fn write_delta(delta: &Self::Delta, writer: &mut Writer);
+    fn read_delta(reader: &mut Reader) -> anyhow::Result<Self::Delta>;
+}
+
+impl Compact for Projectile {
+    fn id(&self) -> Id {
+        self.id
+    }
+    fn write(&self, writer: &mut Writer) {
+        writer.entity(&self.entity);
+        writer.id(self.owner_id);
+    }
+    fn read(reader: &mut Reader) -> anyhow::Result<Self> {
+        Ok(Self {
+            entity: reader.entity()?,
+            owner_id: reader.id()?,
+        })
+    }
+    fn write_delta(delta: &EntityDelta, writer: &mut Writer) {
+        writer.entity_delta(delta);
+    }
+    fn read_delta(reader: &mut Reader) -> anyhow::Result<EntityDelta> {
+        reader.entity_delta()
+    }
+}
+
+impl Compact for Player {
+    fn id(&self) -> Id {
+        self.id
+    }
+    fn write(&self, writer: &mut Writer) {
+        writer.entity(&self.entity);
+        writer.u8(self.projectile.is_some() as u8 | (self.last_hit.is_some() as u8) << 1);
+        if let Some(projectile) = &self.projectile {
+            projectile.write(writer);
+        }
+        writer.action(&self.action);
+        if let Some(id) = self.last_hit {
+            writer.id(id);
+        }
+    }
+    fn read(reader: &mut Reader) -> anyhow::Result<Self> {
+        let entity = reader.entity()?;
+        let flags = reader.u8()?;
+        Ok(Self {
+            entity,
+            projectile: if flags & 1 != 0 {
+                Some(Projectile::read(reader)?)
+            } else {
+                None
+            },
+            action: reader.action()?,
+            last_hit: if flags & 2 != 0 {
+                Some(reader.id()?)
+            } else {
+                None
+            },
+        })
+    }
+    fn write_delta(delta: &PlayerDelta, writer: &mut Writer) {
+        writer.u8(delta.entity.is_some() as u8
+            | (delta.projectile.is_some() as u8) << 1
+            | (delta.projectile.as_ref().is_some_and(|p| p.is_some()) as u8) << 2
+            | (delta.action.is_some() as u8) << 3
+            | (delta.last_hit.is_some() as u8) << 4
+            | (delta.last_hit.is_some_and(|id| id.is_some()) as u8) << 5);
+        if let Some(entity) = &delta.entity {
+            writer.entity_delta(entity);
+        }
+        if let Some(Some(projectile)) = &delta.projectile {
+            projectile.write(writer);
+        }
+        if let Some(action) = &delta.action {
+            writer.action(action);
+        }
+        if let Some(Some(id)) = delta.last_hit {
+            writer.id(id);
+        }
+    }
+    fn read_delta(reader: &mut Reader) -> anyhow::Result<PlayerDelta> {
+        let flags = reader.u8()?;
+        Ok(PlayerDelta {
+            entity: if flags & 1 != 0 {
+                Some(reader.entity_delta()?)
+            } else {
+                None
+            },
+            projectile: match (flags & 2 != 0, flags & 4 != 0) {
+                (false, _) => None,
+                (true, false) => Some(None),
+                (true, true) => Some(Some(Projectile::read(reader)?)),
+            },
+            action: if flags & 8 != 0 {
+                Some(reader.action()?)
+            } else {
+                None
+            },
+            last_hit: match (flags & 16 != 0, flags & 32 != 0) {
+                (false, _) => None,
+                (true, false) => Some(None),
+                (true, true) => Some(Some(reader.id()?)),
+            },
+        })
+    }
+}
+
+fn write_map<T: Compact>(map: &MapDelta<T>, writer: &mut Writer) {
+    writer.varint(map.added.len() as u64);
+    for value in map.added.values() {
+        value.write(writer);
+    }
+    writer.varint(map.changed.len() as u64);
+    for (id, delta) in &map.changed {
+        writer.id(*id);
+        T::write_delta(delta, writer);
+    }
+    writer.varint(map.removed.len() as u64);
+    for id in &map.removed {
+        writer.id(*id);
+    }
+}
+
+fn read_map<T: Compact>(reader: &mut Reader) -> anyhow::Result<MapDelta<T>> {
+    let mut result = MapDelta {
+        added: HashMap::new(),
+        changed: HashMap::new(),
+        removed: Vec::new(),
+    };
+    for _ in 0..reader.varint()? {
+        let value = T::read(reader)?;
+        result.added.insert(value.id(), value);
+    }
+    for _ in 0..reader.varint()? {
+        let id = reader.id()?;
+        result.changed.insert(id, T::read_delta(reader)?);
+    }
+    for _ in 0..reader.varint()? {
+        result.removed.push(reader.id()?);
+    }
+    Ok(result)
+}
+
+/// Packs the update into bytes, quantizing positions relative to the world size
+pub fn encode(update: &ModelUpdate, world_size: f32) -> Vec<u8> {
+    let mut writer = Writer {
+        data: Vec::new(),
+        world_size,
+    };
+    writer.varint(update.id);
+    writer.varint(update.base.map_or(0, |base| base + 1));
+    match &update.delta.rules {
+        Some(rules) => {
+            writer.u8(1);
+            writer.bytes(&bincode::serialize(rules).unwrap());
+        }
+        None => writer.u8(0),
+    }
+    writer.f32(update.delta.current_time);
+    write_map(&update.delta.players, &mut writer);
+    write_map(&update.delta.projectiles, &mut writer);
+    writer.data
+}
+
+/// Reverse of `encode`, world size is taken from the base snapshot unless rules have changed
+pub fn decode(
+    data: &[u8],
+    base_world_size: impl FnOnce(u64) -> Option<f32>,
+) -> anyhow::Result<ModelUpdate> {
+    let mut reader = Reader {
+        data,
+        world_size: 0.0,
+    };
+    let id = reader.varint()?;
+    let base = reader.varint()?.checked_sub(1);
+    let rules: Option<Rules> = if reader.u8()? != 0 {
+        Some(bincode::deserialize(reader.bytes()?)?)
+    } else {
+        None
+    };
+    reader.world_size = match (&rules, base) {
+        (Some(rules), _) => rules.world_size,
+        (None, Some(base)) => match base_world_size(base) {
+            Some(world_size) => world_size,
+            None => anyhow::bail!("Unknown base snapshot {}", base),
+        },
+        (None, None) => anyhow::bail!("Full snapshot without rules"),
+    };
+    let current_time = reader.f32()?;
+    let players = read_map(&mut reader)?;
+    let projectiles = read_map(&mut reader)?;
+    Ok(ModelUpdate {
+        id,
+        base,
+        delta: ModelDelta {
+            rules,
+            current_time,
+            players,
+            projectiles,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WORLD_SIZE: f32 = 100.0;
+    const COORD_STEP: f32 = WORLD_SIZE / 65536.0;
+    const VEL_STEP: f32 = VEL_RANGE / i16::MAX as f32;
+    const SIZE_STEP: f32 = SIZE_RANGE / u16::MAX as f32;
+    const TARGET_VEL_STEP: f32 = TARGET_VEL_RANGE / i16::MAX as f32;
+
+    fn round_trip<T>(write: impl FnOnce(&mut Writer), read: impl FnOnce(&mut Reader) -> T) -> T {
+        let mut writer = Writer {
+            data: Vec::new(),
+            world_size: WORLD_SIZE,
+        };
+        write(&mut writer);
+        let mut reader = Reader {
+            data: &writer.data,
+            world_size: WORLD_SIZE,
+        };
+        let result = read(&mut reader);
+        assert!(reader.data.is_empty(), "{} bytes left", reader.data.len());
+        result
+    }
+
+    /// Distance between coordinates the short way around the world
+    fn wrapped_error(a: f32, b: f32) -> f32 {
+        let d = (a - b).rem_euclid(WORLD_SIZE);
+        d.min(WORLD_SIZE - d)
+    }
+
+    fn assert_close(a: Vec2<f32>, b: Vec2<f32>, step: f32) {
+        let error = (a - b).len();
+        assert!(error <= step, "{:?} decoded as {:?}", a, b);
+    }
+
+    fn assert_close_pos(a: Vec2<f32>, b: Vec2<f32>) {
+        let error = wrapped_error(a.x, b.x).max(wrapped_error(a.y, b.y));
+        assert!(
+            error <= COORD_STEP / 2.0 + 1e-5,
+            "{:?} decoded as {:?}",
+            a,
+            b
+        );
+    }
+
+    fn assert_close_entity(a: &Entity, b: &Entity) {
+        assert_eq!(a.id, b.id);
+        assert_close_pos(a.pos, b.pos);
+        assert_close(a.vel, b.vel, VEL_STEP);
+        assert!((a.size - b.size).abs() <= SIZE_STEP / 2.0 + 1e-5);
+    }
+
+    fn assert_close_model(a: &ModelMessage, b: &ModelMessage) {
+        assert_eq!(a.rules, b.rules);
+        assert_eq!(a.current_time, b.current_time);
+        assert_eq!(
+            a.players.keys().collect::<HashSet<_>>(),
+            b.players.keys().collect::<HashSet<_>>()
+        );
+        for (id, a) in &a.players {
+            let b = &b.players[id];
+            assert_close_entity(&a.entity, &b.entity);
+            assert_eq!(a.action.shoot, b.action.shoot);
+            assert_close(a.action.target_vel, b.action.target_vel, TARGET_VEL_STEP);
+            assert_close_pos(a.action.aim, b.action.aim);
+            assert_eq!(a.last_hit, b.last_hit);
+            match (&a.projectile, &b.projectile) {
+                (Some(a), Some(b)) => assert_close_entity(&a.entity, &b.entity),
+                (None, None) => {}
+                _ => panic!("Projectile of {} got lost or made up", id),
+            }
+        }
+        assert_eq!(
+            a.projectiles.keys().collect::<HashSet<_>>(),
+            b.projectiles.keys().collect::<HashSet<_>>()
+        );
+        for (id, a) in &a.projectiles {
+            assert_close_entity(&a.entity, &b.projectiles[id].entity);
+        }
+    }
+
+    #[test]
+    fn coords_are_within_half_a_step() {
+        for value in [0.0, 0.0001, 12.345, 50.0, 99.999, 99.9999] {
+            let decoded = round_trip(|writer| writer.coord(value), |reader| reader.coord());
+            let decoded = decoded.unwrap();
+            assert!(
+                wrapped_error(value, decoded) <= COORD_STEP / 2.0 + 1e-5,
+                "{} decoded as {}",
+                value,
+                decoded,
+            );
+        }
+    }
+
+    #[test]
+    fn coords_wrap_around_the_world() {
+        for (value, expected) in [
+            (WORLD_SIZE, 0.0),
+            (-0.0001, 0.0),
+            (-3.0, WORLD_SIZE - 3.0),
+            (WORLD_SIZE + 3.0, 3.0),
+        ] {
+            let decoded = round_trip(|writer| writer.coord(value), |reader| reader.coord());
+            let decoded = decoded.unwrap();
+            assert!(
+                (0.0..WORLD_SIZE).contains(&decoded),
+                "{} is outside",
+                decoded
+            );
+            assert!(
+                wrapped_error(expected, decoded) <= COORD_STEP / 2.0 + 1e-5,
+                "{} decoded as {}",
+                value,
+                decoded,
+            );
+        }
+    }
+
+    #[test]
+    fn values_in_range_are_within_half_a_step() {
+        for value in [0.0, 1.0, -1.0, 33.3, -127.9, VEL_RANGE, -VEL_RANGE] {
+            let decoded = round_trip(
+                |writer| writer.signed(value, VEL_RANGE),
+                |reader| reader.signed(VEL_RANGE),
+            );
+            assert!((value - decoded.unwrap()).abs() <= VEL_STEP / 2.0 + 1e-5);
+        }
+        for value in [0.0, Food::SIZE, 1.0, 127.99, SIZE_RANGE] {
+            let decoded = round_trip(
+                |writer| writer.unsigned(value, SIZE_RANGE),
+                |reader| reader.unsigned(SIZE_RANGE),
+            );
+            assert!((value - decoded.unwrap()).abs() <= SIZE_STEP / 2.0 + 1e-5);
+        }
+    }
+
+    #[test]
+    fn values_out_of_range_are_sent_in_full() {
+        for value in [
+            VEL_RANGE + 0.001,
+            -200.0,
+            Projectile::speed(0.001),
+            f32::INFINITY,
+        ] {
+            let decoded = round_trip(
+                |writer| writer.signed(value, VEL_RANGE),
+                |reader| reader.signed(VEL_RANGE),
+            );
+            assert_eq!(decoded.unwrap(), value);
+        }
+        let decoded = round_trip(
+            |writer| writer.signed(f32::NAN, VEL_RANGE),
+            |reader| reader.signed(VEL_RANGE),
+        );
+        assert!(decoded.unwrap().is_nan());
+    }
+
+    #[test]
+    fn varints_round_trip() {
+        for value in [0, 1, 0x7f, 0x80, 300, u32::MAX as u64, u64::MAX] {
+            let decoded = round_trip(|writer| writer.varint(value), |reader| reader.varint());
+            assert_eq!(decoded.unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn truncated_message_is_an_error() {
+        let mut encoder = DeltaEncoder::new();
+        encoder.encoding = Encoding::Compact;
+        let data = match encoder.encode(ModelMessage::default()) {
+            EncodedUpdate::Compact(data) => base64::decode(&data).unwrap(),
+            EncodedUpdate::Readable(_) => unreachable!(),
+        };
+        for len in 0..data.len() {
+            assert!(decode(&data[..len], |_| None).is_err());
+        }
+    }
+
+    #[test]
+    fn updates_round_trip_within_quantization() {
+        let mut encoder = DeltaEncoder::new();
+        encoder.encoding = Encoding::Compact;
+        let mut decoder = DeltaDecoder::new();
+        let mut player = Player::new(Id::new(), vec2(WORLD_SIZE - 0.0001, 0.0));
+        player.action = Action {
+            target_vel: vec2(0.6, -0.8),
+            shoot: true,
+            aim: vec2(3.0, 4.0),
+        };
+        let mut model = ModelMessage {
+            rules: Rules {
+                world_size: WORLD_SIZE,
+                ..default()
+            },
+            current_time: 2.0,
+            players: hashmap! { player.id => player.clone() },
+            projectiles: HashMap::new(),
+        };
+        let decoded = decoder.decode(encoder.encode(model.clone())).unwrap();
+        assert_close_model(&model, &decoded);
+
+        encoder.ack(decoder.ack());
+        // Released from a small charge, so faster than velocities are quantized for
+        let projectile = Projectile {
+            entity: Entity {
+                id: Id::new(),
+                pos: vec2(0.0001, WORLD_SIZE / 2.0),
+                vel: vec2(Projectile::speed(0.01), 0.0),
+                size: 0.01,
+            },
+            owner_id: player.id,
+        };
+        model.projectiles.insert(projectile.id, projectile.clone());
+        model.players.get_mut(&player.id).unwrap().entity.pos = vec2(0.5, WORLD_SIZE - 0.5);
+        model.current_time = 2.05;
+        let decoded = decoder.decode(encoder.encode(model.clone())).unwrap();
+        assert_eq!(decoder.full_snapshots, 1);
+        assert_close_model(&model, &decoded);
+        assert_eq!(decoded.projectiles[&projectile.id].vel, projectile.vel);
+    }
+}
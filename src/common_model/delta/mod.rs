@@ -0,0 +1,517 @@
+use super::*;
+
+use std::collections::VecDeque;
+
+mod compact;
+
+/// Wire format of state updates, chosen by each client
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    /// Plain JSON, handy for debugging
+    Readable,
+    /// Quantized binary, see `compact::encode`
+    Compact,
+}
+
+impl std::str::FromStr for Encoding {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "readable" => Ok(Encoding::Readable),
+            "compact" => Ok(Encoding::Compact),
+            _ => anyhow::bail!("Unknown encoding {:?}, expected readable or compact", s),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum EncodedUpdate {
+    Readable(Box<ModelUpdate>),
+    /// Base64 since messages still go through the JSON transport
+    Compact(String),
+}
+
+/// State of the world relative to a snapshot the client already has
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ModelUpdate {
+    pub id: u64,
+    /// Snapshot this update is relative to, `None` for a full snapshot
+    pub base: Option<u64>,
+    delta: ModelDelta,
+}
+
+/// Changes of a single value, `None` when nothing has changed
+trait Diff: Clone {
+    type Delta;
+    fn diff(&self, new: &Self) -> Option<Self::Delta>;
+    fn apply(&mut self, delta: Self::Delta);
+}
+
+fn changed<T: PartialEq + Clone>(old: &T, new: &T) -> Option<T> {
+    if old != new {
+        Some(new.clone())
+    } else {
+        None
+    }
+}
+
+/// Lets `Some(None)` survive serialization, meaning the value was cleared
+fn double_option<'de, T: Deserialize<'de>, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Option<T>>, D::Error> {
+    Deserialize::deserialize(deserializer).map(Some)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct EntityDelta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pos: Option<Vec2<f32>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    vel: Option<Vec2<f32>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    size: Option<f32>,
+}
+
+impl Diff for Entity {
+    type Delta = EntityDelta;
+    fn diff(&self, new: &Self) -> Option<EntityDelta> {
+        let delta = EntityDelta {
+            pos: changed(&self.pos, &new.pos),
+            vel: changed(&self.vel, &new.vel),
+            size: changed(&self.size, &new.size),
+        };
+        if delta.pos.is_none() && delta.vel.is_none() && delta.size.is_none() {
+            None
+        } else {
+            Some(delta)
+        }
+    }
+    fn apply(&mut self, delta: EntityDelta) {
+        if let Some(pos) = delta.pos {
+            self.pos = pos;
+        }
+        if let Some(vel) = delta.vel {
+            self.vel = vel;
+        }
+        if let Some(size) = delta.size {
+            self.size = size;
+        }
+    }
+}
+
+impl Diff for Projectile {
+    type Delta = EntityDelta;
+    fn diff(&self, new: &Self) -> Option<EntityDelta> {
+        self.entity.diff(&new.entity)
+    }
+    fn apply(&mut self, delta: EntityDelta) {
+        self.entity.apply(delta);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct PlayerDelta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    entity: Option<EntityDelta>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "double_option"
+    )]
+    projectile: Option<Option<Projectile>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    action: Option<Action>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "double_option"
+    )]
+    last_hit: Option<Option<Id>>,
+}
+
+impl Diff for Player {
+    type Delta = PlayerDelta;
+    fn diff(&self, new: &Self) -> Option<PlayerDelta> {
+        let delta = PlayerDelta {
+            entity: self.entity.diff(&new.entity),
+            projectile: changed(&self.projectile, &new.projectile),
+            action: changed(&self.action, &new.action),
+            last_hit: changed(&self.last_hit, &new.last_hit),
+        };
+        if delta.entity.is_none()
+            && delta.projectile.is_none()
+            && delta.action.is_none()
+            && delta.last_hit.is_none()
+        {
+            None
+        } else {
+            Some(delta)
+        }
+    }
+    fn apply(&mut self, delta: PlayerDelta) {
+        if let Some(entity) = delta.entity {
+            self.entity.apply(entity);
+        }
+        if let Some(projectile) = delta.projectile {
+            self.projectile = projectile;
+        }
+        if let Some(action) = delta.action {
+            self.action = action;
+        }
+        if let Some(last_hit) = delta.last_hit {
+            self.last_hit = last_hit;
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(bound(
+    serialize = "T: Serialize, T::Delta: Serialize",
+    deserialize = "T: Deserialize<'de>, T::Delta: Deserialize<'de>"
+))]
+struct MapDelta<T: Diff> {
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    added: HashMap<Id, T>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    changed: HashMap<Id, T::Delta>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    removed: Vec<Id>,
+}
+
+impl<T: Diff> MapDelta<T> {
+    fn new(base: Option<&HashMap<Id, T>>, new: &HashMap<Id, T>) -> Self {
+        let mut result = Self {
+            added: HashMap::new(),
+            changed: HashMap::new(),
+            removed: Vec::new(),
+        };
+        for (id, value) in new {
+            match base.and_then(|base| base.get(id)) {
+                Some(old) => {
+                    if let Some(delta) = old.diff(value) {
+                        result.changed.insert(*id, delta);
+                    }
+                }
+                None => {
+                    result.added.insert(*id, value.clone());
+                }
+            }
+        }
+        if let Some(base) = base {
+            result.removed = base
+                .keys()
+                .filter(|id| !new.contains_key(id))
+                .copied()
+                .collect();
+        }
+        result
+    }
+    fn apply(self, map: &mut HashMap<Id, T>) {
+        for id in self.removed {
+            map.remove(&id);
+        }
+        for (id, delta) in self.changed {
+            if let Some(value) = map.get_mut(&id) {
+                value.apply(delta);
+            }
+        }
+        map.extend(self.added);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct ModelDelta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rules: Option<Rules>,
+    current_time: f32,
+    players: MapDelta<Player>,
+    projectiles: MapDelta<Projectile>,
+}
+
+impl ModelDelta {
+    fn new(base: Option<&ModelMessage>, new: &ModelMessage) -> Self {
+        Self {
+            rules: match base {
+                Some(base) => changed(&base.rules, &new.rules),
+                None => Some(new.rules.clone()),
+            },
+            current_time: new.current_time,
+            players: MapDelta::new(base.map(|base| &base.players), &new.players),
+            projectiles: MapDelta::new(base.map(|base| &base.projectiles), &new.projectiles),
+        }
+    }
+    fn apply(self, model: &mut ModelMessage) {
+        if let Some(rules) = self.rules {
+            model.rules = rules;
+        }
+        model.current_time = self.current_time;
+        self.players.apply(&mut model.players);
+        self.projectiles.apply(&mut model.projectiles);
+    }
+}
+
+/// Server side of delta compression, remembering snapshots sent to a single client
+pub struct DeltaEncoder {
+    pub encoding: Encoding,
+    next_id: u64,
+    sent: VecDeque<(u64, ModelMessage)>,
+    acked: Option<u64>,
+}
+
+impl DeltaEncoder {
+    /// Snapshots older than this are forgotten, clients acking them get a full one
+    const HISTORY: usize = 64;
+
+    pub fn new() -> Self {
+        Self {
+            encoding: Encoding::Readable,
+            next_id: 0,
+            sent: VecDeque::new(),
+            acked: None,
+        }
+    }
+
+    /// Client confirmed having given snapshot, `None` asks for a full one
+    pub fn ack(&mut self, id: Option<u64>) {
+        self.acked = id;
+        if let Some(id) = id {
+            self.sent.retain(|(sent_id, _)| *sent_id >= id);
+        }
+    }
+
+    pub fn encode(&mut self, model: ModelMessage) -> EncodedUpdate {
+        let base = self
+            .acked
+            .and_then(|acked| self.sent.iter().find(|(id, _)| *id == acked));
+        let update = ModelUpdate {
+            id: self.next_id,
+            base: base.map(|(id, _)| *id),
+            delta: ModelDelta::new(base.map(|(_, base)| base), &model),
+        };
+        let update = match self.encoding {
+            Encoding::Readable => EncodedUpdate::Readable(Box::new(update)),
+            Encoding::Compact => EncodedUpdate::Compact(base64::encode(compact::encode(
+                &update,
+                model.rules.world_size,
+            ))),
+        };
+        self.sent.push_back((self.next_id, model));
+        while self.sent.len() > Self::HISTORY {
+            self.sent.pop_front();
+        }
+        self.next_id += 1;
+        update
+    }
+}
+
+/// Client side of delta compression
+pub struct DeltaDecoder {
+    received: VecDeque<(u64, ModelMessage)>,
+    pub full_snapshots: usize,
+}
+
+impl DeltaDecoder {
+    const HISTORY: usize = 64;
+
+    pub fn new() -> Self {
+        Self {
+            received: VecDeque::new(),
+            full_snapshots: 0,
+        }
+    }
+
+    /// Returns `None` if the base snapshot is unknown, a full one is requested with next ack then
+    pub fn decode(&mut self, update: EncodedUpdate) -> Option<ModelMessage> {
+        let update = match update {
+            EncodedUpdate::Readable(update) => *update,
+            EncodedUpdate::Compact(data) => {
+                let received = &self.received;
+                let update = base64::decode(&data)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|data| {
+                        compact::decode(&data, |base| {
+                            received
+                                .iter()
+                                .find(|(id, _)| *id == base)
+                                .map(|(_, model)| model.rules.world_size)
+                        })
+                    });
+                match update {
+                    Ok(update) => update,
+                    Err(e) => {
+                        warn!("Failed to decode state update: {}", e);
+                        self.received.clear();
+                        return None;
+                    }
+                }
+            }
+        };
+        let mut model = match update.base {
+            Some(base) => match self.received.iter().find(|(id, _)| *id == base) {
+                Some((_, model)) => model.clone(),
+                None => {
+                    warn!("Got update relative to unknown snapshot {}", base);
+                    self.received.clear();
+                    return None;
+                }
+            },
+            None => {
+                self.full_snapshots += 1;
+                ModelMessage::default()
+            }
+        };
+        update.delta.apply(&mut model);
+        self.received.push_back((update.id, model.clone()));
+        while self.received.len() > Self::HISTORY {
+            self.received.pop_front();
+        }
+        Some(model)
+    }
+
+    /// Latest snapshot received, to be sent back to the server
+    pub fn ack(&self) -> Option<u64> {
+        self.received.back().map(|(id, _)| *id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model(players: &[&Player], projectiles: &[&Projectile]) -> ModelMessage {
+        ModelMessage {
+            rules: default(),
+            current_time: 1.5,
+            players: players
+                .iter()
+                .map(|&player| (player.id, player.clone()))
+                .collect(),
+            projectiles: projectiles
+                .iter()
+                .map(|&projectile| (projectile.id, projectile.clone()))
+                .collect(),
+        }
+    }
+
+    fn projectile(owner_id: Id, pos: Vec2<f32>) -> Projectile {
+        Projectile {
+            entity: Entity {
+                id: Id::new(),
+                pos,
+                vel: vec2(3.0, -4.0),
+                size: 0.5,
+            },
+            owner_id,
+        }
+    }
+
+    /// Goes through JSON the way updates travel to clients
+    fn transmit(update: EncodedUpdate) -> EncodedUpdate {
+        serde_json::from_str(&serde_json::to_string(&update).unwrap()).unwrap()
+    }
+
+    fn base(update: &EncodedUpdate) -> Option<u64> {
+        match update {
+            EncodedUpdate::Readable(update) => update.base,
+            EncodedUpdate::Compact(_) => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn first_update_is_full() {
+        let player = Player::new(Id::new(), vec2(10.0, 20.0));
+        let shot = projectile(player.id, vec2(11.0, 20.0));
+        let state = model(&[&player], &[&shot]);
+        let mut encoder = DeltaEncoder::new();
+        let mut decoder = DeltaDecoder::new();
+        let update = transmit(encoder.encode(state.clone()));
+        assert_eq!(base(&update), None);
+        assert_eq!(decoder.decode(update), Some(state));
+        assert_eq!(decoder.full_snapshots, 1);
+        assert_eq!(decoder.ack(), Some(0));
+    }
+
+    #[test]
+    fn updates_are_relative_to_acked_state() {
+        let mut a = Player::new(Id::new(), vec2(10.0, 20.0));
+        let b = Player::new(Id::new(), vec2(50.0, 60.0));
+        let c = Player::new(Id::new(), vec2(70.0, 80.0));
+        let old_shot = projectile(b.id, vec2(51.0, 60.0));
+        let mut encoder = DeltaEncoder::new();
+        let mut decoder = DeltaDecoder::new();
+        decoder.decode(transmit(encoder.encode(model(&[&a, &b], &[&old_shot]))));
+        encoder.ack(decoder.ack());
+
+        // Moved, started charging a shot and hit someone, while b left and c came in
+        a.entity.pos = vec2(12.0, 21.0);
+        a.action.shoot = true;
+        a.projectile = Some(projectile(a.id, a.entity.pos));
+        a.last_hit = Some(b.id);
+        let new_shot = projectile(c.id, vec2(71.0, 80.0));
+        let state = model(&[&a, &c], &[&new_shot]);
+        let update = transmit(encoder.encode(state.clone()));
+        assert_eq!(base(&update), Some(0));
+        assert_eq!(decoder.decode(update), Some(state));
+        encoder.ack(decoder.ack());
+
+        // Cleared values have to be told apart from unchanged ones
+        a.action.shoot = false;
+        a.projectile = None;
+        a.last_hit = None;
+        let state = model(&[&a, &c], &[]);
+        let update = transmit(encoder.encode(state.clone()));
+        assert_eq!(base(&update), Some(1));
+        assert_eq!(decoder.decode(update), Some(state));
+        assert_eq!(decoder.full_snapshots, 1);
+    }
+
+    #[test]
+    fn unchanged_state_is_an_empty_delta() {
+        let player = Player::new(Id::new(), vec2(10.0, 20.0));
+        let state = model(&[&player], &[]);
+        let mut encoder = DeltaEncoder::new();
+        encoder.encode(state.clone());
+        encoder.ack(Some(0));
+        match encoder.encode(state) {
+            EncodedUpdate::Readable(update) => {
+                assert!(update.delta.rules.is_none());
+                assert!(update.delta.players.added.is_empty());
+                assert!(update.delta.players.changed.is_empty());
+                assert!(update.delta.players.removed.is_empty());
+            }
+            EncodedUpdate::Compact(_) => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn unknown_base_falls_back_to_full_update() {
+        let player = Player::new(Id::new(), vec2(10.0, 20.0));
+        let mut encoder = DeltaEncoder::new();
+        encoder.encode(model(&[&player], &[]));
+        encoder.ack(Some(0));
+        let state = model(&[], &[]);
+        let update = transmit(encoder.encode(state.clone()));
+        assert_eq!(base(&update), Some(0));
+
+        // Client that missed the base, like after a reconnect, asks for a full update
+        let mut decoder = DeltaDecoder::new();
+        assert_eq!(decoder.decode(update), None);
+        assert_eq!(decoder.ack(), None);
+        encoder.ack(decoder.ack());
+        let update = transmit(encoder.encode(state.clone()));
+        assert_eq!(base(&update), None);
+        assert_eq!(decoder.decode(update), Some(state));
+    }
+
+    #[test]
+    fn forgotten_base_gets_full_update() {
+        let player = Player::new(Id::new(), vec2(10.0, 20.0));
+        let state = model(&[&player], &[]);
+        let mut encoder = DeltaEncoder::new();
+        for _ in 0..=DeltaEncoder::HISTORY {
+            encoder.encode(state.clone());
+        }
+        encoder.ack(Some(0));
+        assert_eq!(base(&encoder.encode(state)), None);
+    }
+}
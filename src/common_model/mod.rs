@@ -1,10 +1,23 @@
 use super::*;
 
+pub mod bot;
+mod delta;
+mod snapshot;
+
+pub use bot::{BotBrain, BotPersonality, BotView};
+pub use delta::{DeltaDecoder, DeltaEncoder, EncodedUpdate, Encoding, ModelUpdate};
+pub use snapshot::{SessionSnapshot, WorldSnapshot};
+
+use std::collections::VecDeque;
+
 pub mod prelude {
-    pub use super::{Action, ClientMessage, Id, Rules, ServerMessage};
+    pub use super::{
+        Action, BotPersonality, ClientMessage, Encoding, Hello, Id, Rules, ServerMessage,
+        StateUpdate,
+    };
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Entity {
     pub id: Id,
     pub pos: Vec2<f32>,
@@ -63,7 +76,7 @@ impl Entity {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Action {
     pub target_vel: Vec2<f32>,
     pub shoot: bool,
@@ -80,17 +93,35 @@ impl Default for Action {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub struct Id(usize);
 
+static NEXT_ID: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(1);
+
 impl Id {
     pub fn new() -> Self {
-        static NEXT_ID: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(1);
         Id(NEXT_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed))
     }
+    /// Makes sure newly generated ids never collide with this one
+    fn reserve(self) {
+        NEXT_ID.fetch_max(self.0 + 1, std::sync::atomic::Ordering::Relaxed);
+    }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+impl std::fmt::Display for Id {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        std::fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl std::str::FromStr for Id {
+    type Err = std::num::ParseIntError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(Id)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Player {
     pub entity: Entity,
     pub projectile: Option<Projectile>,
@@ -132,15 +163,27 @@ impl Player {
             last_hit: None,
         }
     }
-    fn update(&mut self, delta_time: f32, rules: &Rules) -> Option<Projectile> {
-        self.add_mass(-Self::DEATH_SPEED * delta_time);
-
+    /// Moves the player according to its action, also used by clients to predict their own player
+    pub fn update_movement(&mut self, delta_time: f32, rules: &Rules) {
         let mut target_vel = self.action.target_vel.clamp(1.0) * Self::MAX_SPEED;
         if self.action.shoot {
             target_vel = target_vel.clamp(Self::MAX_AIMING_SPEED);
         }
         self.entity.vel += (target_vel - self.entity.vel).clamp(Self::ACCELERATION * delta_time);
         self.entity.update(delta_time, rules);
+    }
+    /// Puts the projectile in front of the player standing at given position, heading for the aim
+    fn aim(&self, pos: Vec2<f32>, projectile: &mut Projectile, rules: &Rules) {
+        let mut dr = rules.normalize_delta(self.action.aim - pos);
+        if dr.len() > self.entity.size {
+            dr = dr.normalize();
+        }
+        projectile.pos = pos + dr * self.entity.size;
+        projectile.entity.vel = dr * Projectile::speed(projectile.entity.size);
+    }
+    fn update(&mut self, delta_time: f32, rules: &Rules) -> Option<Projectile> {
+        self.add_mass(-Self::DEATH_SPEED * delta_time);
+        self.update_movement(delta_time, rules);
 
         if self.action.shoot {
             if self.projectile.is_none() {
@@ -161,14 +204,9 @@ impl Player {
             me.add_mass(-Self::PROJECTILE_COST_SPEED * delta_time);
         }
 
-        if let Some(ref mut projectile) = self.projectile {
-            let mut dr = rules.normalize_delta(self.action.aim - self.entity.pos);
-            if dr.len() > self.entity.size {
-                dr = dr.normalize();
-            }
-            projectile.pos = self.entity.pos + dr * self.entity.size;
-            projectile.entity.vel =
-                dr * Projectile::UNIT_SIZE_VELOCITY * projectile.entity.size.powf(-0.5);
+        if let Some(mut projectile) = self.projectile.take() {
+            self.aim(self.entity.pos, &mut projectile, rules);
+            self.projectile = Some(projectile);
         }
 
         if self.action.shoot {
@@ -179,7 +217,7 @@ impl Player {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Projectile {
     pub entity: Entity,
     pub owner_id: Id,
@@ -235,28 +273,85 @@ impl Projectile {
     const UNIT_SIZE_VELOCITY: f32 = 20.0;
     const DEATH_SPEED: f32 = 0.1;
     const STRENGTH: f32 = 2.0;
+    /// Speed of a projectile released with given size
+    pub fn speed(size: f32) -> f32 {
+        Self::UNIT_SIZE_VELOCITY * size.powf(-0.5)
+    }
     fn update(&mut self, delta_time: f32, rules: &Rules) {
         self.add_mass(-Self::DEATH_SPEED * delta_time);
         self.entity.update(delta_time, rules);
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
 pub struct Rules {
     pub world_size: f32,
-    pub bots_count: usize,
+    /// Distance around the player within which clients are told about the world
+    pub interest_radius: f32,
+    /// State updates pushed to every client per second
+    pub snapshot_rate: f32,
+    /// Number of combatants to keep in the world, bots filling in for missing humans
+    pub target_population: usize,
+    /// Time before a dead bot respawns, in seconds
+    pub bot_respawn_delay: f32,
+    /// Names of bot personalities, assigned to bots in turn
+    pub bot_personalities: Vec<String>,
+    /// Time without input after which a player is warned, in seconds
+    pub afk_warn_time: f32,
+    /// Time without input after which a player is removed from the world, in seconds
+    pub afk_despawn_time: f32,
+    /// Time without input after which the client is disconnected, in seconds
+    pub afk_disconnect_time: f32,
 }
 
 impl Default for Rules {
     fn default() -> Self {
         Self {
             world_size: 100.0,
-            bots_count: 5,
+            interest_radius: 40.0,
+            snapshot_rate: 20.0,
+            target_population: 6,
+            bot_respawn_delay: 3.0,
+            bot_personalities: vec![BotPersonality::DEFAULT.to_owned()],
+            afk_warn_time: 60.0,
+            afk_despawn_time: 90.0,
+            afk_disconnect_time: 300.0,
         }
     }
 }
 
 impl Rules {
+    /// Catches values the game can not work with
+    pub fn check(&self) -> Result<(), String> {
+        for (name, value) in [
+            ("world_size", self.world_size),
+            ("interest_radius", self.interest_radius),
+            ("snapshot_rate", self.snapshot_rate),
+            ("afk_warn_time", self.afk_warn_time),
+            ("afk_despawn_time", self.afk_despawn_time),
+            ("afk_disconnect_time", self.afk_disconnect_time),
+        ] {
+            if !value.is_finite() || value <= 0.0 {
+                return Err(format!("{} must be positive, got {}", name, value));
+            }
+        }
+        if !self.bot_respawn_delay.is_finite() || self.bot_respawn_delay < 0.0 {
+            return Err(format!(
+                "bot_respawn_delay can not be negative, got {}",
+                self.bot_respawn_delay
+            ));
+        }
+        if self.afk_warn_time >= self.afk_despawn_time
+            || self.afk_despawn_time >= self.afk_disconnect_time
+        {
+            return Err(format!(
+                "afk_warn_time < afk_despawn_time < afk_disconnect_time must hold, got {}, {} and {}",
+                self.afk_warn_time, self.afk_despawn_time, self.afk_disconnect_time
+            ));
+        }
+        Ok(())
+    }
     pub fn normalize_pos(&self, pos: Vec2<f32>) -> Vec2<f32> {
         let mut pos = pos;
         while pos.x > self.world_size {
@@ -289,6 +384,8 @@ impl Rules {
 pub enum FoodEvent {
     Add(Food),
     Remove(Id),
+    /// Food still exists, but is no longer of interest to the client
+    OutOfView(Id),
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -300,48 +397,192 @@ pub struct Scores {
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum Event {
     Food(FoodEvent),
-    PlayerName { player_id: Id, name: String },
+    PlayerName {
+        player_id: Id,
+        name: String,
+    },
     ScoresUpdate(HashMap<Id, Scores>),
+    SpectatorsUpdate(HashSet<Id>),
+    Shot {
+        pos: Vec2<f32>,
+    },
+    PlayerDied {
+        player_id: Id,
+        pos: Vec2<f32>,
+    },
+    ProjectileDestroyed {
+        pos: Vec2<f32>,
+    },
+    Chat {
+        sender: Id,
+        text: String,
+        /// Model time the message was sent at
+        time: f32,
+        channel: ChatChannel,
+    },
+}
+
+/// Who gets to read a chat message
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum ChatChannel {
+    All,
+    /// Players only talk to players, spectators to spectators
+    Team,
+    /// Only the sender and the given player
+    Whisper(Id),
+}
+
+impl Event {
+    /// Where in the world it happened, for events that only matter nearby
+    pub fn pos(&self) -> Option<Vec2<f32>> {
+        match *self {
+            Event::Shot { pos }
+            | Event::PlayerDied { pos, .. }
+            | Event::ProjectileDestroyed { pos } => Some(pos),
+            _ => None,
+        }
+    }
+}
+
+struct Bot {
+    id: Id,
+    personality: String,
+    brain: Box<dyn BotBrain>,
+    respawn_time: Option<f32>,
 }
 
 pub struct Model {
     pub rules: Rules,
+    pub seed: u64,
     pub current_time: f32,
+    pub current_tick: u64,
     pub players: HashMap<Id, Player>,
     pub projectiles: HashMap<Id, Projectile>,
     pub food: Vec<Food>,
     pub events: Events<Event>,
     scores: HashMap<Id, Scores>,
+    spectators: HashSet<Id>,
     player_names: HashMap<Id, String>,
-    bots: Vec<Id>,
+    bot_personalities: Vec<BotPersonality>,
+    bots: Vec<Bot>,
+    rng: rand_pcg::Pcg64,
+    /// Positions of players after each of the last ticks, latest at the back
+    history: VecDeque<HashMap<Id, Vec2<f32>>>,
+    /// How many ticks behind the server each client sees the world
+    rewind: HashMap<Id, usize>,
 }
 
 impl Model {
     pub const TICKS_PER_SECOND: f64 = 60.0;
     pub const MAX_FOOD_EXTRA: f32 = 10.0;
+    /// Longest time shots are rewound for a lagging shooter, in seconds
+    pub const MAX_REWIND: f32 = 0.2;
 
-    pub fn new(rules: Rules) -> Self {
+    pub fn new(rules: Rules, bot_personalities: Vec<BotPersonality>) -> Self {
+        let seed = global_rng().gen();
         let mut result = Self {
             rules,
+            seed,
             current_time: 0.0,
+            current_tick: 0,
             players: HashMap::new(),
             projectiles: HashMap::new(),
             food: Vec::new(),
             events: Events::new(),
             player_names: HashMap::new(),
             scores: HashMap::new(),
+            spectators: HashSet::new(),
+            bot_personalities,
             bots: Vec::new(),
+            rng: rand_pcg::Pcg64::seed_from_u64(seed),
+            history: VecDeque::new(),
+            rewind: HashMap::new(),
         };
-        for _ in 0..result.rules.bots_count {
-            result.add_bot();
-        }
+        result.fill_bots();
         result
     }
 
+    fn bot_personality(&self, name: &str) -> BotPersonality {
+        match BotPersonality::find(&self.bot_personalities, name) {
+            Some(personality) => personality.clone(),
+            None => {
+                warn!("Unknown bot personality {:?}", name);
+                BotPersonality::find(&BotPersonality::builtin(), BotPersonality::DEFAULT)
+                    .unwrap()
+                    .clone()
+            }
+        }
+    }
+
     fn add_bot(&mut self) {
         let id = self.new_player();
-        self.set_player_name(id, format!("Bot#{}", id.0));
-        self.bots.push(id);
+        let personality = if self.rules.bot_personalities.is_empty() {
+            BotPersonality::DEFAULT
+        } else {
+            &self.rules.bot_personalities[self.bots.len() % self.rules.bot_personalities.len()]
+        };
+        let personality = self.bot_personality(personality);
+        self.set_player_name(id, format!("Bot#{} ({})", id.0, personality.name));
+        self.bots.push(Bot {
+            id,
+            personality: personality.name.clone(),
+            brain: personality.brain(),
+            respawn_time: None,
+        });
+    }
+
+    fn remove_bot(&mut self) {
+        let index = self
+            .bots
+            .iter()
+            .rposition(|bot| !self.players.contains_key(&bot.id))
+            .unwrap_or(self.bots.len() - 1);
+        let bot = self.bots.remove(index);
+        self.disconnect(bot.id);
+    }
+
+    /// Adds or removes bots so that together with humans there are `target_population` combatants
+    fn fill_bots(&mut self) {
+        let humans = self.scores.len() - self.bots.len();
+        let target_bots = self.rules.target_population.saturating_sub(humans);
+        while self.bots.len() < target_bots {
+            self.add_bot();
+        }
+        while self.bots.len() > target_bots {
+            self.remove_bot();
+        }
+    }
+
+    pub fn set_target_population(&mut self, target_population: usize) {
+        info!("Target population set to {}", target_population);
+        self.rules.target_population = target_population;
+        self.fill_bots();
+    }
+
+    /// Changes the rules in the middle of a round
+    pub fn set_rules(&mut self, rules: Rules) {
+        self.rules = rules;
+        self.fill_bots();
+    }
+
+    /// Starts a new round with given rules, everyone stays but loses their player and scores
+    pub fn restart(&mut self, rules: Rules) {
+        self.rules = rules;
+        self.players.clear();
+        self.projectiles.clear();
+        self.food.clear();
+        self.history.clear();
+        for scores in self.scores.values_mut() {
+            *scores = Scores {
+                kills: 0,
+                deaths: 0,
+            };
+        }
+        for bot in &mut self.bots {
+            bot.respawn_time = None;
+        }
+        self.fill_bots();
+        self.scores_updated();
     }
 
     fn spawn(&mut self, id: Id) {
@@ -350,8 +591,8 @@ impl Model {
             Player::new(
                 id,
                 vec2(
-                    global_rng().gen_range(0.0, self.rules.world_size),
-                    global_rng().gen_range(0.0, self.rules.world_size),
+                    self.rng.gen_range(0.0, self.rules.world_size),
+                    self.rng.gen_range(0.0, self.rules.world_size),
                 ),
             ),
         );
@@ -377,37 +618,159 @@ impl Model {
         self.scores_updated();
         id
     }
+    /// Removes the player from the world, keeping its place on the scoreboard
+    pub fn despawn(&mut self, id: Id) {
+        self.players.remove(&id);
+    }
+    /// Stops the player of a disconnected client where it is
+    pub fn freeze(&mut self, id: Id) {
+        if let Some(player) = self.players.get_mut(&id) {
+            player.action = default();
+        }
+    }
     pub fn disconnect(&mut self, id: Id) {
         self.scores.remove(&id);
         self.players.remove(&id);
         self.player_names.remove(&id);
+        self.rewind.remove(&id);
         self.scores_updated();
+        if self.spectators.remove(&id) {
+            self.spectators_updated();
+        }
+    }
+    /// Removes player from the world and scoreboard, leaving only the name
+    fn spectate(&mut self, id: Id) {
+        if self.spectators.insert(id) {
+            self.players.remove(&id);
+            self.scores.remove(&id);
+            self.scores_updated();
+            self.spectators_updated();
+        }
+    }
+    fn stop_spectating(&mut self, id: Id) {
+        if self.spectators.remove(&id) {
+            self.scores.insert(
+                id,
+                Scores {
+                    kills: 0,
+                    deaths: 0,
+                },
+            );
+            self.scores_updated();
+            self.spectators_updated();
+        }
+    }
+    pub fn is_spectating(&self, id: Id) -> bool {
+        self.spectators.contains(&id)
+    }
+    pub fn player_name(&self, id: Id) -> Option<&str> {
+        self.player_names.get(&id).map(|name| name.as_str())
+    }
+    /// Player whose name the text starts with, followed by a space or nothing, with the name
+    pub fn find_player_by_prefix(&self, text: &str) -> Option<(Id, &str)> {
+        self.player_names
+            .iter()
+            .filter(|(_, name)| {
+                text.strip_prefix(name.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with(' '))
+            })
+            .max_by_key(|(_, name)| name.len())
+            .map(|(id, name)| (*id, name.as_str()))
+    }
+    pub fn chat(&mut self, sender: Id, channel: ChatChannel, text: String) {
+        self.events.fire(Event::Chat {
+            sender,
+            text,
+            time: self.current_time,
+            channel,
+        });
     }
     fn scores_updated(&mut self) {
         self.events.fire(Event::ScoresUpdate(self.scores.clone()));
     }
+    fn spectators_updated(&mut self) {
+        self.events
+            .fire(Event::SpectatorsUpdate(self.spectators.clone()));
+    }
+    fn max_rewind_ticks() -> usize {
+        (Self::MAX_REWIND * Self::TICKS_PER_SECOND as f32) as usize
+    }
+    /// How many ticks back given client sees the world, limited by the history kept
+    fn rewind_ticks(&self, id: Id) -> usize {
+        let rewind = self.rewind.get(&id).copied().unwrap_or(0);
+        rewind.min(self.history.len())
+    }
+    /// Positions of players given number of ticks back, `None` for the present
+    fn seen(
+        history: &VecDeque<HashMap<Id, Vec2<f32>>>,
+        rewind: usize,
+    ) -> Option<&HashMap<Id, Vec2<f32>>> {
+        rewind
+            .checked_sub(1)
+            .and_then(|back| history.iter().rev().nth(back))
+    }
     pub fn tick(&mut self) {
+        self.current_tick += 1;
         self.update(1.0 / Self::TICKS_PER_SECOND as f32);
     }
     fn update(&mut self, delta_time: f32) {
+        let rewinds: HashMap<Id, usize> = self
+            .players
+            .keys()
+            .chain(
+                self.projectiles
+                    .values()
+                    .map(|projectile| &projectile.owner_id),
+            )
+            .map(|&id| (id, self.rewind_ticks(id)))
+            .collect();
         let rules = &self.rules;
+        let history = &self.history;
         self.current_time += delta_time;
         for player in self.players.values_mut() {
-            if let Some(projectile) = player.update(delta_time, rules) {
+            if let Some(mut projectile) = player.update(delta_time, rules) {
+                // Released from where the shooter was in the world it saw,
+                // then flown for as long as the shooter is behind
+                let rewind = rewinds[&player.id];
+                if let Some(&pos) =
+                    Self::seen(history, rewind).and_then(|seen| seen.get(&player.id))
+                {
+                    player.aim(pos, &mut projectile, rules);
+                    projectile.pos = rules.normalize_pos(
+                        projectile.pos + projectile.vel * rewind as f32 * delta_time,
+                    );
+                }
+                self.events.fire(Event::Shot {
+                    pos: projectile.pos,
+                });
                 self.projectiles.insert(projectile.id, projectile);
             }
         }
         for projectile in self.projectiles.values_mut() {
             projectile.update(delta_time, rules);
         }
+        // Projectiles hit players the way their shooter saw both of them, as they were
+        // the same number of ticks back, not where they are now
         for projectile in self.projectiles.values_mut() {
+            let rewind = rewinds[&projectile.owner_id];
+            let seen = Self::seen(history, rewind);
+            let pos = projectile.pos;
+            // Flying straight, so it was this far back along its way
+            projectile.pos = rules.normalize_pos(pos - projectile.vel * rewind as f32 * delta_time);
             for player in self.players.values_mut() {
                 if projectile.owner_id != player.id {
-                    if projectile.hit(player, Projectile::STRENGTH, rules) {
+                    let player_pos = player.pos;
+                    if let Some(&seen_pos) = seen.and_then(|seen| seen.get(&player.id)) {
+                        player.pos = seen_pos;
+                    }
+                    let hit = projectile.hit(player, Projectile::STRENGTH, rules);
+                    player.pos = player_pos;
+                    if hit {
                         player.last_hit = Some(projectile.owner_id);
                     }
                 }
             }
+            projectile.pos = pos;
         }
         fn iter_pairs<T>(mut v: Vec<&mut T>, mut f: impl FnMut(&mut T, &mut T)) {
             for i in 1..v.len() {
@@ -428,21 +791,19 @@ impl Model {
             < Self::MAX_FOOD_EXTRA
         {
             let pos = vec2(
-                global_rng().gen_range(0.0, rules.world_size),
-                global_rng().gen_range(0.0, rules.world_size),
+                self.rng.gen_range(0.0, rules.world_size),
+                self.rng.gen_range(0.0, rules.world_size),
             );
             const N: usize = 10;
             let mut n = N;
             for _ in 0..5 {
-                n = min(n, global_rng().gen_range(1, N));
+                n = min(n, self.rng.gen_range(1, N));
             }
             for _ in 0..n {
                 let food = Food::new(
                     rules.normalize_pos(
-                        pos + vec2(
-                            global_rng().gen_range(-1.0, 1.0),
-                            global_rng().gen_range(-1.0, 1.0),
-                        ) / 5.0,
+                        pos + vec2(self.rng.gen_range(-1.0, 1.0), self.rng.gen_range(-1.0, 1.0))
+                            / 5.0,
                     ),
                     rules,
                 );
@@ -472,12 +833,23 @@ impl Model {
                     }
                 }
                 scores_updated = true;
+                self.events.fire(Event::PlayerDied {
+                    player_id: player.id,
+                    pos: player.pos,
+                });
             }
         }
 
         self.players.retain(|_, e| e.alive());
-        self.projectiles.retain(|_, e| e.alive());
         let events = &mut self.events;
+        self.projectiles.retain(|_, e| {
+            if e.alive() {
+                true
+            } else {
+                events.fire(Event::ProjectileDestroyed { pos: e.pos });
+                false
+            }
+        });
         self.food.retain(|e| {
             if e.alive() {
                 true
@@ -490,90 +862,102 @@ impl Model {
             self.scores_updated();
         }
 
-        let player_count = self.scores.len() - self.bots.len();
-        for i in 0..self.bots.len() {
-            let id = self.bots[i];
-            if player_count <= 1 && !self.players.contains_key(&id) {
-                self.spawn(id);
+        self.fill_bots();
+        let mut respawned_bots = Vec::new();
+        for bot in &mut self.bots {
+            if self.players.contains_key(&bot.id) {
+                continue;
             }
-            if self.players.contains_key(&id) {
-                let action = self.think_bot(id);
-                self.players.get_mut(&id).unwrap().action = action;
+            let respawn_time = *bot
+                .respawn_time
+                .get_or_insert(self.current_time + self.rules.bot_respawn_delay);
+            if self.current_time >= respawn_time {
+                bot.respawn_time = None;
+                respawned_bots.push(bot.id);
             }
         }
+        for id in respawned_bots {
+            self.spawn(id);
+        }
+        let view = BotView {
+            rules: &self.rules,
+            current_time: self.current_time,
+            players: &self.players,
+            projectiles: &self.projectiles,
+            food: &self.food,
+        };
+        let mut actions = Vec::new();
+        for bot in &mut self.bots {
+            if let Some(me) = self.players.get(&bot.id) {
+                actions.push((bot.id, bot.brain.think(&view, me)));
+            }
+        }
+        for (id, action) in actions {
+            self.players.get_mut(&id).unwrap().action = action;
+        }
+
+        self.history.push_back(
+            self.players
+                .iter()
+                .map(|(&id, player)| (id, player.pos))
+                .collect(),
+        );
+        while self.history.len() > Self::max_rewind_ticks() {
+            self.history.pop_front();
+        }
     }
     pub fn handle(&mut self, player_id: Id, message: ClientMessage) {
         match message {
-            ClientMessage::Action(action) => {
+            ClientMessage::Action {
+                action, view_time, ..
+            } => {
                 if let Some(player) = self.players.get_mut(&player_id) {
                     player.action = action;
                 }
+                if let Some(view_time) = view_time {
+                    let behind = (self.current_time - view_time) * Self::TICKS_PER_SECOND as f32;
+                    self.rewind.insert(
+                        player_id,
+                        (behind.round().max(0.0) as usize).min(Self::max_rewind_ticks()),
+                    );
+                }
             }
             ClientMessage::Spawn => {
+                self.stop_spectating(player_id);
                 if !self.players.contains_key(&player_id) {
                     self.spawn(player_id);
                 }
             }
+            ClientMessage::Spectate => {
+                self.spectate(player_id);
+            }
+            ClientMessage::Follow(_)
+            | ClientMessage::Look(_)
+            | ClientMessage::SetEncoding(_)
+            | ClientMessage::Hello(_)
+            | ClientMessage::Resume(_)
+            | ClientMessage::Chat(_)
+            | ClientMessage::ListRooms
+            | ClientMessage::JoinRoom(_)
+            | ClientMessage::CreateRoom(_)
+            | ClientMessage::Kick(_)
+            | ClientMessage::SetRule { .. }
+            | ClientMessage::StartMatch
+            | ClientMessage::AdminLogin(_) => {}
             ClientMessage::SetName(name) => {
                 self.set_player_name(player_id, name);
             }
         }
     }
-    fn think_bot(&self, id: Id) -> Action {
-        let me: &Player = self
-            .players
-            .values()
-            .find(|player| player.id == id)
-            .unwrap();
-        let closest_food = self.food.iter().min_by(|a, b| {
-            self.rules
-                .normalize_delta(a.pos - me.pos)
-                .len()
-                .partial_cmp(&self.rules.normalize_delta(b.pos - me.pos).len())
-                .unwrap()
-        });
-        let closest_enemy = self
-            .players
-            .values()
-            .filter(|player| player.id != me.id)
-            .min_by(|a, b| {
-                self.rules
-                    .normalize_delta(a.pos - me.pos)
-                    .len()
-                    .partial_cmp(&self.rules.normalize_delta(b.pos - me.pos).len())
-                    .unwrap()
-            });
-        let mut shoot = None;
-        if let Some(e) = closest_enemy {
-            if self.rules.normalize_delta(e.pos - me.pos).len() < 17.0 {
-                shoot = Some(e.pos);
-            }
-        }
-        if me.size < 0.7 {
-            shoot = None;
-        }
-        if let Some(p) = &me.projectile {
-            if p.size > 0.4 {
-                shoot = None;
-            }
-        }
-        Action {
-            target_vel: closest_food.map(|f| f.pos).unwrap_or(vec2(0.0, 0.0)) - me.pos,
-            shoot: shoot.is_some(),
-            aim: shoot
-                .or(me.projectile.as_ref().map(|p| p.pos))
-                .unwrap_or(vec2(0.0, 0.0)),
-        }
-    }
 }
 
 impl Default for Model {
     fn default() -> Self {
-        Self::new(default())
+        Self::new(default(), BotPersonality::builtin())
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
 pub struct ModelMessage {
     pub rules: Rules,
     pub current_time: f32,
@@ -602,20 +986,123 @@ impl Model {
             });
         }
         result.push(Event::ScoresUpdate(self.scores.clone()));
+        result.push(Event::SpectatorsUpdate(self.spectators.clone()));
         result
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct ServerMessage {
-    pub model: ModelMessage,
+/// First message of each side, telling what protocol they speak
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Hello {
+    pub protocol_version: u32,
+    pub build: String,
+}
+
+impl Hello {
+    /// Bumped on every incompatible change of `ServerMessage` or `ClientMessage`
+    pub const PROTOCOL_VERSION: u32 = 11;
+
+    pub fn current() -> Self {
+        Self {
+            protocol_version: Self::PROTOCOL_VERSION,
+            build: env!("CARGO_PKG_VERSION").to_owned(),
+        }
+    }
+
+    /// Why the server can not talk to a client that said this hello
+    pub fn incompatibility(&self) -> Option<String> {
+        if self.protocol_version == Self::PROTOCOL_VERSION {
+            return None;
+        }
+        Some(format!(
+            "Your game (build {}, protocol {}) is not compatible with the server (build {}, protocol {}).\n\
+             Please reload the page or update the game.",
+            self.build,
+            self.protocol_version,
+            env!("CARGO_PKG_VERSION"),
+            Self::PROTOCOL_VERSION,
+        ))
+    }
+}
+
+/// `Welcome` and `Rejected` must never change, so that clients of any version understand them
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ServerMessage {
+    /// Reply to `ClientMessage::Hello` of an accepted client, updates follow
+    Welcome(Hello),
+    /// Client can not play on this server, or not anymore, with a reason to show to the user
+    Rejected(String),
+    Update(StateUpdate),
+    /// Sent after welcome, lets the client get its player back with `ClientMessage::Resume`
+    ResumeToken(String),
+    /// Reply to `ClientMessage::ListRooms`
+    Rooms(Vec<RoomInfo>),
+    /// Client is now in the room with given id, updates of the new world follow
+    RoomJoined(String),
+    /// Something to show the user, like why a request was refused
+    Notice(String),
+    /// Place in the queue of a client waiting to play on a full server, `None` once it may spawn
+    Queue(Option<usize>),
+}
+
+/// Room as seen in the room list
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RoomInfo {
+    pub id: String,
+    /// Connected clients, bots are not counted
+    pub players: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StateUpdate {
+    pub model: EncodedUpdate,
     pub events: Vec<Event>,
     pub client_player_id: Id,
+    /// Sequence number of the latest input of this client applied by the server
+    pub last_input: Option<u64>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum ClientMessage {
-    Action(Action),
+    Action {
+        action: Action,
+        /// Increases with every input sent, older inputs arriving late are dropped
+        seq: u64,
+        /// Server time of the state the client shows, its shots hit what it saw
+        view_time: Option<f32>,
+        /// Latest state update received, see `DeltaDecoder::ack`
+        ack: Option<u64>,
+    },
     Spawn,
     SetName(String),
+    /// Watch the game without taking part in it
+    Spectate,
+    /// Player the spectator's camera follows, the rest of the world is sent around them
+    Follow(Option<Id>),
+    /// Where the free camera of a spectator following nobody is, the world is sent around it
+    Look(Vec2<f32>),
+    /// How the server should encode state updates for this client
+    SetEncoding(Encoding),
+    /// Must be the first message, nothing else is sent until the server welcomes the client
+    Hello(Hello),
+    /// Sent right after hello by a reconnecting client to take its old player back
+    Resume(String),
+    /// Text typed by the player, starting with `/t` for team chat or `/w name` for whispers
+    Chat(String),
+    ListRooms,
+    /// Leave the current world and join the room with given id as a new player
+    JoinRoom(String),
+    /// Make a private room with given rules and join it as its host
+    CreateRoom(Rules),
+    /// Host only: send the player back to the main room
+    Kick(Id),
+    /// Host only: change a field of `Rules` for the next round, value given as JSON
+    SetRule {
+        name: String,
+        value: String,
+    },
+    /// Host only: start a new round with the latest rules
+    StartMatch,
+    /// Secret set on the server with `--admin-key`, admins skip the join queue
+    AdminLogin(String),
 }
@@ -0,0 +1,136 @@
+use super::*;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct BotSnapshot {
+    id: Id,
+    personality: String,
+    respawn_time: Option<f32>,
+}
+
+/// Client playing when the snapshot was taken, able to resume its player after restart
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SessionSnapshot {
+    pub player_id: Id,
+    pub muted: bool,
+}
+
+/// Full state of the world, enough to continue the game after server restart
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WorldSnapshot {
+    pub version: u32,
+    pub build: String,
+    rules: Rules,
+    seed: u64,
+    current_time: f32,
+    current_tick: u64,
+    players: HashMap<Id, Player>,
+    projectiles: HashMap<Id, Projectile>,
+    food: Vec<Food>,
+    scores: HashMap<Id, Scores>,
+    spectators: HashSet<Id>,
+    player_names: HashMap<Id, String>,
+    bots: Vec<BotSnapshot>,
+    rng: rand_pcg::Pcg64,
+    /// Resume tokens of the clients, filled in by the server
+    pub sessions: HashMap<String, SessionSnapshot>,
+}
+
+impl WorldSnapshot {
+    /// Bumped every time the layout of the snapshot changes
+    pub const VERSION: u32 = 3;
+}
+
+impl Model {
+    pub fn snapshot(&self) -> WorldSnapshot {
+        WorldSnapshot {
+            version: WorldSnapshot::VERSION,
+            build: env!("CARGO_PKG_VERSION").to_owned(),
+            rules: self.rules.clone(),
+            seed: self.seed,
+            current_time: self.current_time,
+            current_tick: self.current_tick,
+            players: self.players.clone(),
+            projectiles: self.projectiles.clone(),
+            food: self.food.clone(),
+            scores: self.scores.clone(),
+            spectators: self.spectators.clone(),
+            player_names: self.player_names.clone(),
+            bots: self
+                .bots
+                .iter()
+                .map(|bot| BotSnapshot {
+                    id: bot.id,
+                    personality: bot.personality.clone(),
+                    respawn_time: bot.respawn_time,
+                })
+                .collect(),
+            rng: self.rng.clone(),
+            sessions: HashMap::new(),
+        }
+    }
+
+    /// Continues the world from a snapshot.
+    ///
+    /// Bots are kept along with humans whose clients can resume their sessions,
+    /// projectiles of everyone stay in flight.
+    pub fn restore(snapshot: WorldSnapshot, bot_personalities: Vec<BotPersonality>) -> Self {
+        let kept: HashSet<Id> = snapshot
+            .bots
+            .iter()
+            .map(|bot| bot.id)
+            .chain(snapshot.sessions.values().map(|session| session.player_id))
+            .collect();
+        let mut players = snapshot.players;
+        players.retain(|id, _| kept.contains(id));
+        let mut scores = snapshot.scores;
+        scores.retain(|id, _| kept.contains(id));
+        let mut spectators = snapshot.spectators;
+        spectators.retain(|id| kept.contains(id));
+        let mut player_names = snapshot.player_names;
+        player_names.retain(|id, _| kept.contains(id));
+        for id in players
+            .keys()
+            .chain(snapshot.projectiles.keys())
+            .chain(scores.keys())
+            .chain(spectators.iter())
+            .chain(snapshot.food.iter().map(|food| &food.id))
+        {
+            id.reserve();
+        }
+        let mut result = Self {
+            rules: snapshot.rules,
+            seed: snapshot.seed,
+            current_time: snapshot.current_time,
+            current_tick: snapshot.current_tick,
+            players,
+            projectiles: snapshot.projectiles,
+            food: snapshot.food,
+            events: Events::new(),
+            scores,
+            spectators,
+            player_names,
+            bot_personalities,
+            bots: Vec::new(),
+            rng: snapshot.rng,
+            history: VecDeque::new(),
+            rewind: HashMap::new(),
+        };
+        for bot in snapshot.bots {
+            let personality = result.bot_personality(&bot.personality);
+            result.bots.push(Bot {
+                id: bot.id,
+                personality: personality.name.clone(),
+                brain: personality.brain(),
+                respawn_time: bot.respawn_time,
+            });
+        }
+        result.fill_bots();
+        info!(
+            "Restored world at tick {} with {} bots and {} humans",
+            result.current_tick,
+            result.bots.len(),
+            snapshot.sessions.len(),
+        );
+        result
+    }
+}
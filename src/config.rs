@@ -0,0 +1,116 @@
+use super::*;
+
+/// Problem with the configuration the program is started with
+#[derive(Debug)]
+pub enum ConfigError {
+    Read {
+        path: String,
+        error: std::io::Error,
+    },
+    /// File is not valid JSON or does not fit, with the position of the problem
+    Parse {
+        path: String,
+        error: serde_json::Error,
+    },
+    /// File parses but its values make no sense
+    Invalid {
+        path: String,
+        message: String,
+    },
+    /// Option or environment variable with a value that can not be used
+    BadValue {
+        name: String,
+        value: String,
+        message: String,
+    },
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Read { path, error } => write!(f, "Failed to read {}: {}", path, error),
+            Self::Parse { path, error } => {
+                // Position goes in front, the way compilers report it
+                let message = error.to_string();
+                let message = match message.rsplit_once(" at line ") {
+                    Some((message, _)) => message,
+                    None => &message,
+                };
+                write!(
+                    f,
+                    "{}:{}:{}: {}",
+                    path,
+                    error.line(),
+                    error.column(),
+                    message
+                )
+            }
+            Self::Invalid { path, message } => write!(f, "{}: {}", path, message),
+            Self::BadValue {
+                name,
+                value,
+                message,
+            } => write!(f, "Bad {} {:?}: {}", name, value, message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl ConfigError {
+    /// Status the program exits with, telling configuration problems from crashes
+    pub const EXIT_CODE: i32 = 2;
+}
+
+pub fn load_json<T: for<'de> Deserialize<'de>>(path: &str) -> Result<T, ConfigError> {
+    let content = std::fs::read_to_string(path).map_err(|error| ConfigError::Read {
+        path: path.to_owned(),
+        error,
+    })?;
+    serde_json::from_str(&content).map_err(|error| ConfigError::Parse {
+        path: path.to_owned(),
+        error,
+    })
+}
+
+/// Whether the JSON object in given file has a field with given name
+fn has_field(path: &str, name: &str) -> bool {
+    load_json::<serde_json::Value>(path)
+        .ok()
+        .is_some_and(|value| value.get(name).is_some())
+}
+
+/// Stops the program with a message on bad configuration, instead of panicking
+pub fn or_exit<T>(result: Result<T, ConfigError>) -> T {
+    result.unwrap_or_else(|e| {
+        error!("{}", e);
+        std::process::exit(ConfigError::EXIT_CODE);
+    })
+}
+
+pub fn load_rules(path: &str) -> Result<Rules, ConfigError> {
+    let rules: Rules = load_json(path).map_err(|e| match e {
+        ConfigError::Parse { .. } if has_field(path, "bots_count") => ConfigError::Invalid {
+            path: path.to_owned(),
+            message: "bots_count is not supported anymore, it was a number of bots only. \
+                      Use target_population, which counts humans too"
+                .to_owned(),
+        },
+        e => e,
+    })?;
+    rules.check().map_err(|message| ConfigError::Invalid {
+        path: path.to_owned(),
+        message,
+    })?;
+    Ok(rules)
+}
+
+/// Rules of a public room given as `id=rules.json`
+pub fn load_room(arg: &str) -> Result<(String, Rules), ConfigError> {
+    let (id, path) = arg.split_once('=').ok_or_else(|| ConfigError::BadValue {
+        name: "--room".to_owned(),
+        value: arg.to_owned(),
+        message: "expected id=rules.json".to_owned(),
+    })?;
+    Ok((id.to_owned(), load_rules(path)?))
+}
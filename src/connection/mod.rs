@@ -0,0 +1,114 @@
+use super::*;
+
+#[cfg(not(target_arch = "wasm32"))]
+#[path = "native.rs"]
+mod platform;
+
+#[cfg(target_arch = "wasm32")]
+#[path = "web.rs"]
+mod platform;
+
+/// Bytes gone through a connection so far
+#[derive(Default)]
+pub struct Traffic {
+    inbound: std::sync::atomic::AtomicUsize,
+    outbound: std::sync::atomic::AtomicUsize,
+}
+
+impl Traffic {
+    fn add_inbound(&self, amount: usize) {
+        self.inbound
+            .fetch_add(amount, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    fn add_outbound(&self, amount: usize) {
+        self.outbound
+            .fetch_add(amount, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    pub fn inbound(&self) -> usize {
+        self.inbound.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    pub fn outbound(&self) -> usize {
+        self.outbound.load(std::sync::atomic::Ordering::Relaxed)
+    }
+}
+
+/// Connection to the server, same as `net::client::Connection`, but failing to connect
+/// and losing the connection are reported instead of panicking.
+///
+/// The stream of messages ends when the connection is lost, dropping it hangs up.
+pub struct Connection {
+    socket: platform::Socket,
+    recv: futures::channel::mpsc::UnboundedReceiver<ServerMessage>,
+    traffic: Arc<Traffic>,
+}
+
+impl Connection {
+    pub fn traffic(&self) -> &Traffic {
+        &self.traffic
+    }
+
+    /// Messages sent after the connection is lost are dropped, the stream ending tells about it
+    pub fn send(&mut self, message: ClientMessage) {
+        trace!("Sending message to server: {:?}", message);
+        let data = serde_json::to_vec(&message).unwrap();
+        self.traffic.add_outbound(data.len());
+        if let Err(e) = self.socket.send(data) {
+            warn!("Failed to send message to server: {}", e);
+        }
+    }
+}
+
+impl Stream for Connection {
+    type Item = ServerMessage;
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        self.recv.poll_next_unpin(cx)
+    }
+}
+
+/// Handles messages coming from the server
+struct Receiver {
+    sender: futures::channel::mpsc::UnboundedSender<ServerMessage>,
+    traffic: Arc<Traffic>,
+}
+
+impl Receiver {
+    fn receive(&self, data: &[u8]) {
+        self.traffic.add_inbound(data.len());
+        let message: ServerMessage = match serde_json::from_slice(data) {
+            Ok(message) => message,
+            Err(e) => {
+                warn!("Failed to deserialize message from server: {}", e);
+                return;
+            }
+        };
+        trace!("Got message from server: {:?}", message);
+        // Nobody listens anymore if the connection is being dropped
+        let _ = self.sender.unbounded_send(message);
+    }
+}
+
+/// Connects to the server at given address, failing if it can not be reached
+pub fn connect(addr: &str) -> impl Future<Output = anyhow::Result<Connection>> {
+    let (sender, recv) = futures::channel::mpsc::unbounded();
+    let traffic = Arc::new(Traffic::default());
+    let socket = platform::connect(
+        addr,
+        Receiver {
+            sender,
+            traffic: traffic.clone(),
+        },
+    );
+    async move {
+        Ok(Connection {
+            socket: socket.await?,
+            recv,
+            traffic,
+        })
+    }
+}
@@ -0,0 +1,121 @@
+use super::*;
+
+/// Client side of a ws-rs connection, running its event loop in a thread of its own
+pub struct Socket {
+    broadcaster: ws::Sender,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl Socket {
+    /// There is only the one connection, so broadcasting reaches the server
+    pub fn send(&self, data: Vec<u8>) -> anyhow::Result<()> {
+        self.broadcaster
+            .send(ws::Message::Binary(data))
+            .map_err(|e| anyhow::anyhow!(describe(&e)))
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        // Fails if the event loop already stopped after losing the connection
+        let _ = self.broadcaster.shutdown();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Connection thread panicked");
+            }
+        }
+    }
+}
+
+struct Handler {
+    /// Tells whether the connection opened, taken once it is known
+    opened: Option<futures::channel::oneshot::Sender<Result<(), String>>>,
+    receiver: Receiver,
+}
+
+impl ws::Handler for Handler {
+    fn on_open(&mut self, _: ws::Handshake) -> ws::Result<()> {
+        info!("Connected to the server");
+        if let Some(opened) = self.opened.take() {
+            let _ = opened.send(Ok(()));
+        }
+        Ok(())
+    }
+    fn on_message(&mut self, message: ws::Message) -> ws::Result<()> {
+        self.receiver.receive(&message.into_data());
+        Ok(())
+    }
+    fn on_close(&mut self, code: ws::CloseCode, reason: &str) {
+        info!("Server closed the connection: {:?} {}", code, reason);
+    }
+    fn on_error(&mut self, error: ws::Error) {
+        match self.opened.take() {
+            Some(opened) => {
+                let _ = opened.send(Err(describe(&error)));
+            }
+            None => warn!("Connection error: {}", describe(&error)),
+        }
+    }
+}
+
+/// Display of `ws::Error` relies on the deprecated `description`, which is empty by now
+fn describe(error: &ws::Error) -> String {
+    match &error.kind {
+        ws::ErrorKind::Io(error) => error.to_string(),
+        _ => format!("{:?}", error),
+    }
+}
+
+struct Factory {
+    handler: Option<Handler>,
+}
+
+impl ws::Factory for Factory {
+    type Handler = Handler;
+    fn connection_made(&mut self, _: ws::Sender) -> Handler {
+        self.handler
+            .take()
+            .expect("Only one connection is made by the client")
+    }
+}
+
+pub(super) fn connect(
+    addr: &str,
+    receiver: Receiver,
+) -> impl Future<Output = anyhow::Result<Socket>> {
+    let addr = addr.to_owned();
+    let (opened_sender, opened) = futures::channel::oneshot::channel();
+    let started = (|| -> anyhow::Result<Socket> {
+        let url = addr
+            .parse()
+            .with_context(|| format!("Bad server address {:?}", addr))?;
+        let mut ws = ws::WebSocket::new(Factory {
+            handler: Some(Handler {
+                opened: Some(opened_sender),
+                receiver,
+            }),
+        })
+        .map_err(|e| anyhow::anyhow!(describe(&e)))?;
+        let broadcaster = ws.broadcaster();
+        ws.connect(url).map_err(|e| anyhow::anyhow!(describe(&e)))?;
+        // The event loop stops by itself once the connection is closed
+        let thread = std::thread::spawn(move || {
+            if let Err(e) = ws.run() {
+                error!("Connection stopped with error: {}", describe(&e));
+            }
+        });
+        Ok(Socket {
+            broadcaster,
+            thread: Some(thread),
+        })
+    })();
+    async move {
+        // Dropping the socket on failure stops its thread
+        let socket = started?;
+        match opened.await {
+            Ok(Ok(())) => Ok(socket),
+            Ok(Err(e)) => anyhow::bail!("Failed to connect to {}: {}", addr, e),
+            Err(_) => anyhow::bail!("Connection closed before it opened"),
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use super::*;
+
+/// Browser WebSocket, keeping the handlers it calls alive for as long as it is open
+pub struct Socket {
+    ws: web_sys::WebSocket,
+    on_message: Closure<dyn FnMut(web_sys::MessageEvent)>,
+    /// Handles opening, closing and errors
+    on_event: Closure<dyn FnMut(web_sys::Event)>,
+}
+
+impl Socket {
+    pub fn send(&self, data: Vec<u8>) -> anyhow::Result<()> {
+        self.ws
+            .send_with_u8_array(&data)
+            .map_err(|e| anyhow::anyhow!("{:?}", e))
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        // Handlers are freed along with the socket, the browser must not call them anymore
+        self.ws.set_onopen(None);
+        self.ws.set_onmessage(None);
+        self.ws.set_onclose(None);
+        self.ws.set_onerror(None);
+        if let Err(e) = self.ws.close() {
+            warn!("Failed to close connection: {:?}", e);
+        }
+    }
+}
+
+pub(super) fn connect(
+    addr: &str,
+    receiver: Receiver,
+) -> impl Future<Output = anyhow::Result<Socket>> {
+    let addr = addr.to_owned();
+    let (opened_sender, opened) = futures::channel::oneshot::channel();
+    let started = (|| -> anyhow::Result<Socket> {
+        let ws = web_sys::WebSocket::new(&addr)
+            .map_err(|e| anyhow::anyhow!("Bad server address {:?}: {:?}", addr, e))?;
+        ws.set_binary_type(web_sys::BinaryType::Arraybuffer);
+        // Dropped when the connection closes, which ends the stream of messages
+        let receiver = Rc::new(RefCell::new(Some(receiver)));
+        let on_message = Closure::wrap(Box::new({
+            let receiver = receiver.clone();
+            move |event: web_sys::MessageEvent| {
+                let data = match event.data().dyn_into::<js_sys::ArrayBuffer>() {
+                    Ok(data) => js_sys::Uint8Array::new(&data).to_vec(),
+                    Err(_) => {
+                        warn!("Got a message from the server that is not binary");
+                        return;
+                    }
+                };
+                if let Some(receiver) = &*receiver.borrow() {
+                    receiver.receive(&data);
+                }
+            }
+        }) as Box<dyn FnMut(web_sys::MessageEvent)>);
+        let mut opened_sender = Some(opened_sender);
+        let on_event = Closure::wrap(Box::new(move |event: web_sys::Event| {
+            let kind = event.type_();
+            if kind == "open" {
+                info!("Connected to the server");
+                if let Some(opened) = opened_sender.take() {
+                    let _ = opened.send(Ok(()));
+                }
+                return;
+            }
+            // Browsers tell nothing more about what went wrong
+            if let Some(opened) = opened_sender.take() {
+                let _ = opened.send(Err(format!("connection got {} event", kind)));
+            }
+            if kind == "close" {
+                info!("Server closed the connection");
+                receiver.borrow_mut().take();
+            }
+        }) as Box<dyn FnMut(web_sys::Event)>);
+        ws.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+        ws.set_onopen(Some(on_event.as_ref().unchecked_ref()));
+        ws.set_onclose(Some(on_event.as_ref().unchecked_ref()));
+        ws.set_onerror(Some(on_event.as_ref().unchecked_ref()));
+        Ok(Socket {
+            ws,
+            on_message,
+            on_event,
+        })
+    })();
+    async move {
+        // Dropping the socket on failure closes it
+        let socket = started?;
+        match opened.await {
+            Ok(Ok(())) => Ok(socket),
+            Ok(Err(e)) => anyhow::bail!("Failed to connect to {}: {}", addr, e),
+            Err(_) => anyhow::bail!("Connection closed before it opened"),
+        }
+    }
+}
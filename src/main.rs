@@ -5,18 +5,28 @@ use geng::net;
 use geng::prelude::*;
 use log::{debug, error, info, trace, warn};
 
+#[cfg(not(target_arch = "wasm32"))]
+mod bot_client;
 mod client;
 mod common_model;
+mod config;
+mod connection;
 #[cfg(not(target_arch = "wasm32"))]
 mod server;
 
+#[cfg(not(target_arch = "wasm32"))]
+use bot_client::*;
 use client::*;
 use common_model::prelude::*;
+use common_model::{BotBrain, BotView};
+use config::ConfigError;
 #[cfg(not(target_arch = "wasm32"))]
 use server::*;
 
 mod events;
+mod replay;
 use events::*;
+use replay::*;
 
 #[derive(StructOpt, Debug, Clone)]
 pub struct OptsNetOpts {
@@ -29,26 +39,31 @@ pub struct OptsNetOpts {
 }
 
 impl OptsNetOpts {
-    fn get(&self) -> NetOpts {
-        NetOpts {
+    fn get(&self) -> Result<NetOpts, ConfigError> {
+        let port = match (self.port, option_env!("LIFESHOT_PORT")) {
+            (Some(port), _) => port,
+            (None, Some(port)) => port.parse().map_err(|e| ConfigError::BadValue {
+                name: "LIFESHOT_PORT".to_owned(),
+                value: port.to_owned(),
+                message: format!("{}", e),
+            })?,
+            (None, None) => 1154,
+        };
+        Ok(NetOpts {
             host: self
                 .host
                 .as_deref()
                 .or(option_env!("LIFESHOT_HOST"))
                 .unwrap_or("127.0.0.1")
                 .to_owned(),
-            port: self
-                .port
-                .or(option_env!("LIFESHOT_PORT")
-                    .map(|port| port.parse().expect("Failed to parse port")))
-                .unwrap_or(1154),
+            port,
             addr: self
                 .addr
                 .as_deref()
                 .or(option_env!("LIFESHOT_ADDR"))
                 .unwrap_or("ws://127.0.0.1:1154")
                 .to_owned(),
-        }
+        })
     }
 }
 
@@ -65,6 +80,22 @@ pub enum Command {
     ServerOnly,
     #[structopt(name = "with-server")]
     WithServer,
+    /// Run headless bots connecting to the server
+    #[structopt(name = "bots")]
+    Bots {
+        #[structopt(long, default_value = "1")]
+        count: usize,
+        #[structopt(long, default_value = "normal")]
+        personality: String,
+    },
+    /// Compare sizes of state updates in different encodings
+    #[structopt(name = "bench-encoding")]
+    BenchEncoding {
+        #[structopt(long, default_value = "600")]
+        ticks: u64,
+        #[structopt(long, default_value = "20")]
+        population: usize,
+    },
 }
 
 #[derive(StructOpt, Debug, Clone)]
@@ -75,10 +106,57 @@ pub struct Opts {
     net_opts: OptsNetOpts,
     #[structopt(long, default_value = "<noname>")]
     name: String,
+    /// Join as a spectator
+    #[structopt(long)]
+    spectate: bool,
+    /// Join the private room with given invite code, `?invite=CODE` in the web build
+    #[structopt(long)]
+    invite: Option<String>,
+    /// Make a private room and join it as its host
+    #[structopt(long)]
+    create_room: bool,
+    /// Rules of the room made with `--create-room`, server defaults if not given
+    #[structopt(long)]
+    room_rules: Option<String>,
+    /// Encoding of state updates asked from the server, compact or readable
+    #[structopt(long, default_value = "compact")]
+    encoding: Encoding,
     #[structopt(subcommand)]
     command: Option<Command>,
     #[structopt(long)]
     rules: Option<String>,
+    /// Extra public room with its own rules, as id=rules.json, can be repeated
+    #[structopt(long = "room", number_of_values = 1)]
+    rooms: Vec<String>,
+    #[structopt(long)]
+    bots: Option<String>,
+    /// Record a replay of the match to given file
+    #[structopt(long)]
+    record: Option<String>,
+    /// Play back a recorded replay instead of connecting to the server
+    #[structopt(long)]
+    replay: Option<String>,
+    /// Save world snapshot to given file periodically and on shutdown
+    #[structopt(long)]
+    snapshot: Option<String>,
+    /// Continue the world from a previously saved snapshot
+    #[structopt(long)]
+    restore: Option<String>,
+    /// Words to star out in chat, one per line
+    #[structopt(long)]
+    chat_blocklist: Option<String>,
+    /// Most players on the server at once, others wait in a queue spectating
+    #[structopt(long)]
+    max_players: Option<usize>,
+    /// Secret that makes a client an admin, set on the server and given by admin clients
+    #[structopt(long)]
+    admin_key: Option<String>,
+    /// Keep bans in given file, it is reloaded when edited
+    #[structopt(long)]
+    bans: Option<String>,
+    /// Take admin commands on this port, only from the same machine
+    #[structopt(long)]
+    admin_port: Option<u16>,
 }
 
 fn main() {
@@ -93,26 +171,82 @@ fn main() {
     logger::init();
     let opts: Opts = program_args::parse();
     info!("Options used:\n{:#?}", opts);
-    let net_opts = opts.net_opts.get();
+    let net_opts = config::or_exit(opts.net_opts.get());
     if let Some(level) = opts.log_level {
         log::set_max_level(level);
     }
     info!("Net opts:\n{:#?}", net_opts);
     trace!("Initializing");
 
+    #[cfg(not(target_arch = "wasm32"))]
+    let bot_personalities = {
+        let mut bot_personalities = Vec::new();
+        if let Some(filename) = &opts.bots {
+            bot_personalities = config::or_exit(config::load_json(filename));
+        }
+        BotPersonality::merge(bot_personalities)
+    };
+    #[cfg(not(target_arch = "wasm32"))]
+    let rules: Option<Rules> =
+        config::or_exit(opts.rules.as_deref().map(config::load_rules).transpose());
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        match &opts.command {
+            Some(Command::Bots { count, personality }) => {
+                let personality = config::or_exit(
+                    BotPersonality::find(&bot_personalities, personality).ok_or_else(|| {
+                        ConfigError::BadValue {
+                            name: "--personality".to_owned(),
+                            value: personality.clone(),
+                            message: "there is no such bot personality".to_owned(),
+                        }
+                    }),
+                );
+                BotClient::run(&net_opts, *count, personality, opts.encoding);
+                return;
+            }
+            Some(Command::BenchEncoding { ticks, population }) => {
+                bench_encoding(
+                    rules.unwrap_or_default(),
+                    bot_personalities,
+                    *ticks,
+                    *population,
+                );
+                return;
+            }
+            _ => {}
+        }
+    }
+
     #[cfg(target_arch = "wasm32")]
     let server = None::<()>;
     #[cfg(not(target_arch = "wasm32"))]
     let (server, server_handle) = if opts.command.is_some() {
-        let rules;
-        if let Some(filename) = &opts.rules {
-            let content = std::fs::read_to_string(filename).unwrap();
-            rules = serde_json::from_str(&content).unwrap();
-        } else {
-            rules = default();
-        }
-
-        let server = Server::new(&net_opts, rules);
+        let rooms = config::or_exit(
+            opts.rooms
+                .iter()
+                .map(|room| config::load_room(room))
+                .collect(),
+        );
+        let server = config::or_exit(Server::new(
+            &net_opts,
+            ServerConfig {
+                rules,
+                rules_path: opts.rules.clone(),
+                rooms,
+                bot_personalities,
+                record: opts.record.clone(),
+                restore: opts.restore.clone(),
+                snapshot: opts.snapshot.clone(),
+                chat_blocklist: opts.chat_blocklist.clone(),
+                max_players: opts.max_players,
+                admin_key: opts.admin_key.clone(),
+                bans: opts.bans.clone(),
+                console: matches!(opts.command, Some(Command::ServerOnly)),
+                admin_port: opts.admin_port,
+            },
+        ));
         let server_handle = server.handle();
         ctrlc::set_handler({
             let server_handle = server_handle.clone();
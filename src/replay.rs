@@ -0,0 +1,39 @@
+use super::*;
+
+use common_model::{EncodedUpdate, Event};
+
+/// First line of a replay file, stored as JSON so it stays readable by any build
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReplayHeader {
+    pub version: u32,
+    pub build: String,
+    pub rules: Rules,
+    pub seed: u64,
+}
+
+impl ReplayHeader {
+    /// Bumped every time the layout of replay entries changes
+    pub const VERSION: u32 = 9;
+}
+
+/// Replay entries, following the header encoded with bincode
+#[derive(Serialize, Deserialize, Debug)]
+pub enum ReplayEntry {
+    /// Message received from a client during given tick
+    Input {
+        tick: u64,
+        player_id: Id,
+        message: ClientMessage,
+    },
+    /// State of the world after given tick, with events fired since previous frame.
+    ///
+    /// Keyframes hold the whole world, others only what changed since previous frame.
+    Frame {
+        tick: u64,
+        keyframe: bool,
+        update: EncodedUpdate,
+        /// Food, names, scores and spectators at the time, empty unless a keyframe
+        state: Vec<Event>,
+        events: Vec<Event>,
+    },
+}
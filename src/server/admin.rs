@@ -0,0 +1,406 @@
+use super::*;
+
+use std::io::{BufRead, Write};
+
+const HELP: &str = "\
+players               list connected clients with ids and addresses
+kick <id> [reason]    throw a client off the server
+ban <id> [time] [reason]
+                      kick a client and keep it out, for a time like 30m, 12h or 7d if given
+unban <address|token> lift a ban
+bans                  list bans
+mute <id>             drop chat messages of a client, unmute <id> to undo
+say <text>            show a notice to everyone
+rooms                 list running rooms
+room <id>             pick the room the commands below apply to, main by default
+rules [name value]    show the rules of the room, or change one right away
+bots add|remove [n]   add or remove bots in the room
+restart               start a new round in the room
+snapshot [path]       save the main room, to the --snapshot file by default";
+
+/// Commands of the server admin, typed into stdin or sent to the admin port
+#[derive(Clone)]
+pub struct Console {
+    rooms: SharedRooms,
+    bans: Arc<Mutex<Bans>>,
+    snapshot_path: Option<String>,
+    /// Room the room commands apply to
+    room: String,
+}
+
+impl Console {
+    pub fn new(rooms: SharedRooms, bans: Arc<Mutex<Bans>>, snapshot_path: Option<String>) -> Self {
+        Self {
+            rooms,
+            bans,
+            snapshot_path,
+            room: Rooms::MAIN.to_owned(),
+        }
+    }
+
+    /// Runs a command line, returning what to show to the admin
+    pub fn execute(&mut self, line: &str) -> String {
+        let line = line.trim();
+        if line.is_empty() {
+            return String::new();
+        }
+        info!("Admin command: {}", line);
+        let (command, args) = match line.split_once(' ') {
+            Some((command, args)) => (command, args.trim()),
+            None => (line, ""),
+        };
+        let result = match command {
+            "help" => Ok(HELP.to_owned()),
+            "players" => Ok(self.players()),
+            "kick" => self.kick(args, false),
+            "ban" => self.kick(args, true),
+            "unban" => self.unban(args),
+            "bans" => Ok(self.bans()),
+            "mute" => self.mute(args, true),
+            "unmute" => self.mute(args, false),
+            "say" => self.say(args),
+            "rooms" => Ok(self.list_rooms()),
+            "room" => self.pick_room(args),
+            "rules" => self.rules(args),
+            "bots" => self.bots(args),
+            "restart" => self.restart(),
+            "snapshot" => self.snapshot(args),
+            _ => Err(format!("Unknown command {:?}, try help", command)),
+        };
+        result.unwrap_or_else(|e| format!("Error: {}", e))
+    }
+
+    /// Running rooms, main room first
+    fn running_rooms(&self) -> Vec<Arc<Room>> {
+        let mut rooms: Vec<Arc<Room>> = self
+            .rooms
+            .lock()
+            .unwrap()
+            .running
+            .values()
+            .cloned()
+            .collect();
+        rooms.sort_by_key(|room| (room.id != Rooms::MAIN, room.id.clone()));
+        rooms
+    }
+
+    fn room(&self) -> Result<Arc<Room>, String> {
+        self.rooms
+            .lock()
+            .unwrap()
+            .running
+            .get(&self.room)
+            .cloned()
+            .ok_or_else(|| format!("Room {:?} is not running anymore", self.room))
+    }
+
+    /// Room where the client playing as given player is, with the player
+    fn find_player(&self, args: &str) -> Result<(Arc<Room>, Id), String> {
+        let id: Id = args
+            .parse()
+            .map_err(|_| format!("Expected a player id, got {:?}", args))?;
+        self.running_rooms()
+            .into_iter()
+            .find(|room| {
+                let sessions = room.sessions.lock().unwrap();
+                sessions
+                    .active
+                    .values()
+                    .any(|session| session.player_id == id)
+            })
+            .map(|room| (room, id))
+            .ok_or_else(|| format!("No client plays as {}", id))
+    }
+
+    fn players(&self) -> String {
+        let mut lines = vec![format!(
+            "{:>6}  {:<20}  {:<39}  room",
+            "id", "name", "address"
+        )];
+        for room in self.running_rooms() {
+            let model = room.model.lock().unwrap();
+            let sessions = room.sessions.lock().unwrap();
+            let mut active: Vec<&Session> = sessions.active.values().collect();
+            active.sort_by_key(|session| session.player_id);
+            for session in active {
+                let mut flags = String::new();
+                if session.queued.is_some() {
+                    flags += " queued";
+                } else if model.is_spectating(session.player_id) {
+                    flags += " spectating";
+                }
+                if session.admin {
+                    flags += " admin";
+                }
+                if session.muted {
+                    flags += " muted";
+                }
+                lines.push(format!(
+                    "{:>6}  {:<20}  {:<39}  {}{}",
+                    session.player_id,
+                    session.name.as_deref().unwrap_or("<noname>"),
+                    session
+                        .addr()
+                        .map_or("unknown".to_owned(), |addr| addr.to_string()),
+                    room.id,
+                    flags,
+                ));
+            }
+        }
+        lines.join("\n")
+    }
+
+    fn kick(&self, args: &str, ban: bool) -> Result<String, String> {
+        let (id, mut reason) = match args.split_once(' ') {
+            Some((id, reason)) => (id, Some(reason.trim())),
+            None => (args, None),
+        };
+        let (room, id) = self.find_player(id)?;
+        let mut model = room.model.lock().unwrap();
+        let mut sessions = room.sessions.lock().unwrap();
+        let token = sessions.find(id).ok_or("The client just left")?;
+        let session = &sessions.active[&token];
+        let name = session.name.clone().unwrap_or_default();
+        let message = if ban {
+            let mut duration = None;
+            if let Some(text) = reason {
+                let (first, rest) = text.split_once(' ').unwrap_or((text, ""));
+                if let Some(seconds) = ban::parse_duration(first)? {
+                    duration = Some(seconds);
+                    reason = Some(rest.trim()).filter(|rest| !rest.is_empty());
+                }
+            }
+            let expires = match duration {
+                Some(duration) => Some(
+                    ban::now()
+                        .checked_add(duration)
+                        .ok_or("Ban time is too long, leave it out to ban forever")?,
+                ),
+                None => None,
+            };
+            let ban = Ban {
+                addr: session.addr(),
+                token: Some(token),
+                reason: reason.unwrap_or("no reason given").to_owned(),
+                expires,
+            };
+            let message = ban.message();
+            self.bans.lock().unwrap().add(ban);
+            message
+        } else {
+            match reason {
+                Some(reason) => format!("You were kicked from the server: {}", reason),
+                None => "You were kicked from the server".to_owned(),
+            }
+        };
+        sessions.disconnect(&mut model, id, &message);
+        info!("{:?} was thrown off the server by the admin", name);
+        Ok(format!("{} {:?} is gone", id, name))
+    }
+
+    fn unban(&self, key: &str) -> Result<String, String> {
+        if key.is_empty() {
+            return Err("Usage: unban <address|token>".to_owned());
+        }
+        if !self.bans.lock().unwrap().remove(key) {
+            return Err(format!("{} is not banned", key));
+        }
+        info!("Unbanned {}", key);
+        Ok(format!("Unbanned {}", key))
+    }
+
+    fn bans(&self) -> String {
+        let bans = self.bans.lock().unwrap();
+        let lines: Vec<String> = bans.entries().map(|ban| ban.describe()).collect();
+        if lines.is_empty() {
+            return "Nobody is banned".to_owned();
+        }
+        lines.join("\n")
+    }
+
+    fn mute(&self, args: &str, muted: bool) -> Result<String, String> {
+        let (room, id) = self.find_player(args)?;
+        let mut sessions = room.sessions.lock().unwrap();
+        let session = sessions.get_player_mut(id).ok_or("The client just left")?;
+        session.muted = muted;
+        session.send(ServerMessage::Notice(
+            if muted {
+                "You were muted by the admin"
+            } else {
+                "You can chat again"
+            }
+            .to_owned(),
+        ));
+        Ok(format!(
+            "{} is {}",
+            id,
+            if muted { "muted" } else { "unmuted" }
+        ))
+    }
+
+    fn say(&self, text: &str) -> Result<String, String> {
+        if text.is_empty() {
+            return Err("Usage: say <text>".to_owned());
+        }
+        let text = format!("[admin] {}", text);
+        for room in self.running_rooms() {
+            room.sessions.lock().unwrap().notify_all(&text);
+        }
+        Ok("Sent".to_owned())
+    }
+
+    fn list_rooms(&self) -> String {
+        self.running_rooms()
+            .iter()
+            .map(|room| {
+                format!(
+                    "{} {} ({} clients{})",
+                    if room.id == self.room { "*" } else { " " },
+                    room.id,
+                    room.sessions.lock().unwrap().active.len(),
+                    if room.private.is_some() {
+                        ", private"
+                    } else {
+                        ""
+                    },
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn pick_room(&mut self, id: &str) -> Result<String, String> {
+        if id.is_empty() {
+            return Ok(format!("Commands apply to room {}", self.room));
+        }
+        if !self.rooms.lock().unwrap().running.contains_key(id) {
+            return Err(format!("Room {:?} is not running", id));
+        }
+        self.room = id.to_owned();
+        Ok(format!("Commands apply to room {}", self.room))
+    }
+
+    fn rules(&self, args: &str) -> Result<String, String> {
+        let room = self.room()?;
+        let mut model = room.model.lock().unwrap();
+        if args.is_empty() {
+            return Ok(serde_json::to_string_pretty(&model.rules).unwrap());
+        }
+        let (name, value) = args.split_once(' ').ok_or("Usage: rules <name> <value>")?;
+        let value = value.trim();
+        // Admins are trusted with any rules the game can run with, not only what players may set
+        let rules = room::change_rule(&model.rules, name, value)?;
+        rules.check()?;
+        model.set_rules(rules);
+        info!("Admin set {} to {} in room {:?}", name, value, room.id);
+        room.sessions
+            .lock()
+            .unwrap()
+            .notify_all(&format!("Admin set {} to {}", name, value));
+        Ok(format!("Set {} to {}", name, value))
+    }
+
+    fn bots(&self, args: &str) -> Result<String, String> {
+        let (action, count) = match args.split_once(' ') {
+            Some((action, count)) => (action, count.trim()),
+            None => (args, "1"),
+        };
+        let count: usize = count
+            .parse()
+            .map_err(|_| format!("Expected a number of bots, got {:?}", count))?;
+        let room = self.room()?;
+        let mut model = room.model.lock().unwrap();
+        let target_population = match action {
+            "add" => model.rules.target_population + count,
+            "remove" => model.rules.target_population.saturating_sub(count),
+            _ => return Err("Usage: bots add|remove [n]".to_owned()),
+        };
+        model.set_target_population(target_population);
+        Ok(format!("Target population is now {}", target_population))
+    }
+
+    fn restart(&self) -> Result<String, String> {
+        let room = self.room()?;
+        let mut model = room.model.lock().unwrap();
+        let rules = model.rules.clone();
+        model.restart(rules);
+        info!("Admin restarted the round in room {:?}", room.id);
+        room.sessions
+            .lock()
+            .unwrap()
+            .notify_all("Round restarted by the admin, press R to spawn");
+        Ok(format!("Restarted room {}", room.id))
+    }
+
+    fn snapshot(&self, path: &str) -> Result<String, String> {
+        let path = match (path, &self.snapshot_path) {
+            ("", Some(path)) => path.as_str(),
+            ("", None) => return Err("Give a path, or start the server with --snapshot".to_owned()),
+            (path, _) => path,
+        };
+        let main = self.rooms.lock().unwrap().running[Rooms::MAIN].clone();
+        snapshot::save(path, &main.snapshot());
+        Ok(format!("Saved the main room to {:?}", path))
+    }
+}
+
+/// Runs commands typed into stdin until it is closed, printing the replies
+pub fn read_stdin(mut console: Console) {
+    std::thread::spawn(move || {
+        info!("Admin console is ready, type help for the commands");
+        for line in std::io::stdin().lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    error!("Failed to read admin command: {}", e);
+                    return;
+                }
+            };
+            let reply = console.execute(&line);
+            if !reply.is_empty() {
+                println!("{}", reply);
+            }
+        }
+    });
+}
+
+/// Runs commands sent over connections to given port, only reachable from this machine.
+///
+/// Fails if the port is taken.
+pub fn listen(console: Console, port: u16) -> Result<(), ConfigError> {
+    let listener =
+        std::net::TcpListener::bind(("127.0.0.1", port)).map_err(|e| ConfigError::BadValue {
+            name: "--admin-port".to_owned(),
+            value: port.to_string(),
+            message: format!("failed to listen on it: {}", e),
+        })?;
+    info!("Admin console listening on 127.0.0.1:{}", port);
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("Failed to accept admin connection: {}", e);
+                    continue;
+                }
+            };
+            let console = console.clone();
+            std::thread::spawn(move || {
+                if let Err(e) = serve(console, stream) {
+                    warn!("Admin connection failed: {}", e);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+fn serve(mut console: Console, stream: std::net::TcpStream) -> std::io::Result<()> {
+    info!("Admin connected from {}", stream.peer_addr()?);
+    let mut writer = stream.try_clone()?;
+    for line in std::io::BufReader::new(stream).lines() {
+        let reply = console.execute(&line?);
+        writeln!(writer, "{}", reply)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,201 @@
+use super::*;
+
+use std::time::SystemTime;
+
+/// Seconds since the Unix epoch
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Turns durations like `45s`, `30m`, `12h` or `7d` into seconds.
+///
+/// Gives `None` for text that is not a duration, and an error for one too long to count.
+pub fn parse_duration(text: &str) -> Result<Option<u64>, String> {
+    let unit: u64 = match text.chars().last() {
+        Some('s') => 1,
+        Some('m') => 60,
+        Some('h') => 60 * 60,
+        Some('d') => 24 * 60 * 60,
+        _ => return Ok(None),
+    };
+    let count = &text[..text.len() - 1];
+    if count.is_empty() || !count.bytes().all(|c| c.is_ascii_digit()) {
+        return Ok(None);
+    }
+    count
+        .parse::<u64>()
+        .ok()
+        .and_then(|count| count.checked_mul(unit))
+        .map(Some)
+        .ok_or_else(|| format!("{} is too long", text))
+}
+
+/// Client kept out of the server, by its address, its resume token or both
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Ban {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub addr: Option<IpAddr>,
+    /// Resume token of the session the client was banned in.
+    ///
+    /// It only stops that session from being resumed, a new connection gets a new token,
+    /// so it is the address that keeps the client out.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    #[serde(default)]
+    pub reason: String,
+    /// Unix time in seconds when the ban ends, it never does if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<u64>,
+}
+
+impl Ban {
+    fn expired(&self, now: u64) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    fn minutes_left(&self) -> Option<u64> {
+        self.expires
+            .map(|expires| expires.saturating_sub(now()).div_ceil(60))
+    }
+
+    /// What the banned client is shown
+    pub fn message(&self) -> String {
+        let mut message = format!("You are banned from this server: {}", self.reason);
+        if let Some(minutes) = self.minutes_left() {
+            message += &format!(" ({} more minutes)", minutes);
+        }
+        message
+    }
+
+    /// One line summary for the admin
+    pub fn describe(&self) -> String {
+        let mut line = String::new();
+        if let Some(addr) = self.addr {
+            line += &format!("{} ", addr);
+        }
+        if let Some(token) = &self.token {
+            line += &format!("token {} ", token);
+        }
+        match self.minutes_left() {
+            Some(minutes) => line += &format!("for {} more minutes", minutes),
+            None => line += "forever",
+        }
+        format!("{}: {}", line, self.reason)
+    }
+}
+
+/// Bans given by admins, kept in a file if one is given, which is reloaded after editing by hand
+#[derive(Default)]
+pub struct Bans {
+    entries: Vec<Ban>,
+    path: Option<String>,
+    /// Modification time of the file when it was last read or written
+    modified: Option<SystemTime>,
+}
+
+impl Bans {
+    /// Reads bans from given file, starting with none if it does not exist yet
+    pub fn load(path: &str) -> Result<Self, ConfigError> {
+        let mut bans = Self {
+            entries: Vec::new(),
+            path: Some(path.to_owned()),
+            modified: None,
+        };
+        if std::path::Path::new(path).exists() {
+            bans.modified = file::modified(path);
+            bans.entries = config::load_json(path)?;
+        }
+        Ok(bans)
+    }
+
+    fn read(&mut self) -> anyhow::Result<()> {
+        let path = self.path.as_ref().unwrap();
+        self.modified = file::modified(path);
+        let content = std::fs::read_to_string(path)?;
+        self.entries = serde_json::from_str(&content).context("Bad ban list")?;
+        Ok(())
+    }
+
+    /// Reads the file again if it changed since, returns whether it did.
+    ///
+    /// A broken file is reported once, keeping the bans from before.
+    pub fn reload_if_changed(&mut self) -> bool {
+        let path = match &self.path {
+            Some(path) => path.clone(),
+            None => return false,
+        };
+        let modified = file::modified(&path);
+        if modified.is_none() || modified == self.modified {
+            return false;
+        }
+        let old = self.entries.clone();
+        match self.read() {
+            Ok(()) => {
+                info!("Reloaded {} bans from {:?}", self.entries.len(), path);
+                true
+            }
+            Err(e) => {
+                error!("Failed to reload bans, keeping the old ones: {:#}", e);
+                self.entries = old;
+                self.modified = modified;
+                false
+            }
+        }
+    }
+
+    /// Writes the bans that did not expire back to the file
+    fn save(&mut self) {
+        let path = match &self.path {
+            Some(path) => path.clone(),
+            None => return,
+        };
+        let now = now();
+        self.entries.retain(|ban| !ban.expired(now));
+        let entries = &self.entries;
+        let result = file::write_atomic(&path, |writer| {
+            serde_json::to_writer_pretty(writer, entries)?;
+            Ok(())
+        });
+        match result {
+            Ok(()) => self.modified = file::modified(&path),
+            Err(e) => error!("Failed to save bans: {}", e),
+        }
+    }
+
+    /// Bans that did not expire yet
+    pub fn entries(&self) -> impl Iterator<Item = &Ban> {
+        let now = now();
+        self.entries.iter().filter(move |ban| !ban.expired(now))
+    }
+
+    pub fn add(&mut self, ban: Ban) {
+        info!("Banned {}", ban.describe());
+        self.entries.push(ban);
+        self.save();
+    }
+
+    /// Lifts bans of given address or resume token, returns whether there were any
+    pub fn remove(&mut self, key: &str) -> bool {
+        let addr: Option<IpAddr> = key.parse().ok();
+        let count = self.entries.len();
+        self.entries.retain(|ban| {
+            !(addr.is_some() && ban.addr == addr || ban.token.as_deref() == Some(key))
+        });
+        if self.entries.len() == count {
+            return false;
+        }
+        self.save();
+        true
+    }
+
+    /// Ban keeping out a client from given address or with given resume token
+    pub fn find(&self, addr: Option<IpAddr>, token: Option<&str>) -> Option<&Ban> {
+        self.entries().find(|ban| {
+            ban.addr.is_some() && ban.addr == addr
+                || ban.token.is_some() && ban.token.as_deref() == token
+        })
+    }
+}
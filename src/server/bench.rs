@@ -0,0 +1,67 @@
+use super::*;
+
+/// Total size of messages sent to a single client, as they go over the wire
+struct Stream {
+    encoder: DeltaEncoder,
+    acked: bool,
+    total: usize,
+}
+
+impl Stream {
+    fn new(encoding: Encoding, acked: bool) -> Self {
+        let mut encoder = DeltaEncoder::new();
+        encoder.encoding = encoding;
+        Self {
+            encoder,
+            acked,
+            total: 0,
+        }
+    }
+    fn send(&mut self, id: u64, player_id: Id, model: ModelMessage) {
+        let message = ServerMessage::Update(StateUpdate {
+            client_player_id: player_id,
+            model: self.encoder.encode(model),
+            events: Vec::new(),
+            last_input: None,
+        });
+        self.total += serde_json::to_vec(&message).unwrap().len();
+        if self.acked {
+            self.encoder.ack(Some(id));
+        }
+    }
+}
+
+/// Simulates a game with bots, sending state of the whole world every tick in each encoding
+pub fn bench_encoding(
+    rules: Rules,
+    bot_personalities: Vec<BotPersonality>,
+    ticks: u64,
+    population: usize,
+) {
+    let mut model = Model::new(rules, bot_personalities);
+    model.set_target_population(population);
+    let player_id = Id::new();
+    let mut streams = [
+        ("readable, full", Stream::new(Encoding::Readable, false)),
+        ("readable, delta", Stream::new(Encoding::Readable, true)),
+        ("compact, full", Stream::new(Encoding::Compact, false)),
+        ("compact, delta", Stream::new(Encoding::Compact, true)),
+    ];
+    for id in 0..ticks {
+        model.tick();
+        for (_, stream) in &mut streams {
+            stream.send(id, player_id, model.to_message());
+        }
+    }
+    println!(
+        "Average message size over {} ticks with {} players:",
+        ticks, population
+    );
+    for (name, stream) in &streams {
+        println!(
+            "{:>16}: {:>8} bytes",
+            name,
+            stream.total / ticks.max(1) as usize
+        );
+    }
+}
@@ -0,0 +1,118 @@
+use super::*;
+
+use common_model::ChatChannel;
+
+/// Longest chat message, in characters, longer ones are cut
+const MAX_LENGTH: usize = 200;
+
+/// Words nobody gets to see in chat, loaded from a file with one word per line
+#[derive(Default)]
+pub struct Blocklist {
+    words: HashSet<String>,
+}
+
+impl Blocklist {
+    pub fn load(path: &str) -> Result<Self, ConfigError> {
+        let content = std::fs::read_to_string(path).map_err(|error| ConfigError::Read {
+            path: path.to_owned(),
+            error,
+        })?;
+        Ok(Self {
+            words: content
+                .lines()
+                .map(|line| line.trim().to_lowercase())
+                .filter(|word| !word.is_empty())
+                .collect(),
+        })
+    }
+
+    /// Stars out blocked words, ignoring case and punctuation around them
+    fn clean(&self, text: &str) -> String {
+        text.split(' ')
+            .map(|word| {
+                let bare = word.trim_matches(|c: char| !c.is_alphanumeric());
+                if self.words.contains(&bare.to_lowercase()) {
+                    "*".repeat(word.chars().count())
+                } else {
+                    word.to_owned()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Lets a client send a few messages in a row, then one every couple of seconds
+pub struct RateLimit {
+    allowance: f32,
+    last_time: f32,
+}
+
+impl RateLimit {
+    const BURST: f32 = 5.0;
+    const PER_SECOND: f32 = 0.5;
+
+    pub fn new() -> Self {
+        Self {
+            allowance: Self::BURST,
+            last_time: 0.0,
+        }
+    }
+
+    fn allow(&mut self, time: f32) -> bool {
+        self.allowance =
+            (self.allowance + (time - self.last_time) * Self::PER_SECOND).min(Self::BURST);
+        self.last_time = time;
+        if self.allowance < 1.0 {
+            return false;
+        }
+        self.allowance -= 1.0;
+        true
+    }
+}
+
+/// Works out where a message typed by the player goes and what it says.
+///
+/// `/t text` goes to the sender's team, `/w name text` to a single player.
+fn parse(model: &Model, text: &str) -> Result<(ChatChannel, String), String> {
+    let command = match text.strip_prefix('/') {
+        Some(command) => command,
+        None => return Ok((ChatChannel::All, text.to_owned())),
+    };
+    let (name, rest) = command.split_once(' ').unwrap_or((command, ""));
+    match name {
+        "t" | "team" => Ok((ChatChannel::Team, rest.to_owned())),
+        "w" | "whisper" => match model.find_player_by_prefix(rest) {
+            Some((id, name)) => Ok((
+                ChatChannel::Whisper(id),
+                rest[name.len()..].trim_start().to_owned(),
+            )),
+            None => Err(format!("Nobody to whisper to in {:?}", rest)),
+        },
+        _ => Err(format!("Unknown chat command /{}", name)),
+    }
+}
+
+/// Checks a message from the player and lets the others see it, or tells the sender why not
+pub fn handle(
+    model: &mut Model,
+    limit: &mut RateLimit,
+    blocklist: &Blocklist,
+    sender: Id,
+    text: &str,
+) -> Result<(), String> {
+    if !limit.allow(model.current_time) {
+        warn!("Chat message from {:?} dropped by rate limit", sender);
+        return Err("You are sending messages too fast, wait a bit".to_owned());
+    }
+    let text: String = text.trim().chars().take(MAX_LENGTH).collect();
+    let (channel, text) = parse(model, &text).map_err(|e| {
+        warn!("Bad chat message from {:?}: {}", sender, e);
+        e
+    })?;
+    let text = blocklist.clean(text.trim());
+    if !text.is_empty() {
+        model.chat(sender, channel, text);
+    }
+    Ok(())
+}
@@ -0,0 +1,26 @@
+use super::*;
+
+use std::io::Write;
+use std::time::SystemTime;
+
+/// Modification time of the file, none if there is no such file
+pub fn modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Writes to a temporary file next to the path and renames it over,
+/// so that a crash never leaves a half written file behind
+pub fn write_atomic(
+    path: &str,
+    write: impl FnOnce(&mut dyn Write) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let tmp_path = format!("{}.tmp", path);
+    let mut writer = std::io::BufWriter::new(std::fs::File::create(&tmp_path)?);
+    write(&mut writer)?;
+    writer.flush()?;
+    drop(writer);
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
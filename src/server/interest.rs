@@ -0,0 +1,116 @@
+use super::*;
+
+use common_model::{ChatChannel, Entity, Event, FoodEvent};
+
+/// Part of the world a single client gets to know about
+pub struct Interest {
+    /// Player followed by a spectating client
+    pub follow: Option<Id>,
+    /// Free camera of a spectating client following nobody, as last told by the client
+    pub camera: Option<Vec2<f32>>,
+    /// Around what the client is interested, not known before the first update
+    center: Option<Vec2<f32>>,
+    visible_food: HashSet<Id>,
+}
+
+impl Interest {
+    pub fn new() -> Self {
+        Self {
+            follow: None,
+            camera: None,
+            center: None,
+            visible_food: HashSet::new(),
+        }
+    }
+
+    /// Dead players keep looking at where they died, players not spawned yet at the middle
+    /// of the world, spectators with free camera around the camera
+    fn update_center(&mut self, model: &Model, player_id: Id) {
+        let target = match (model.is_spectating(player_id), self.follow) {
+            (true, Some(id)) => Some(id),
+            (true, None) => None,
+            (false, _) => Some(player_id),
+        };
+        match target.and_then(|id| model.players.get(&id)) {
+            Some(player) => self.center = Some(player.pos),
+            None if target.is_none() && self.camera.is_some() => self.center = self.camera,
+            None if self.center.is_none() => {
+                let world_size = model.rules.world_size;
+                self.center = Some(vec2(world_size, world_size) / 2.0);
+            }
+            None => {}
+        }
+    }
+
+    fn in_range(&self, rules: &Rules, pos: Vec2<f32>, size: f32) -> bool {
+        match self.center {
+            Some(center) => {
+                rules.normalize_delta(pos - center).len() < rules.interest_radius + size
+            }
+            None => true,
+        }
+    }
+
+    fn visible(&self, rules: &Rules, entity: &Entity) -> bool {
+        self.in_range(rules, entity.pos, entity.size)
+    }
+
+    /// State and events for the client, with food entering and leaving the view as needed
+    pub fn filter(
+        &mut self,
+        model: &Model,
+        player_id: Id,
+        events: impl IntoIterator<Item = Event>,
+    ) -> (ModelMessage, Vec<Event>) {
+        self.update_center(model, player_id);
+        let rules = &model.rules;
+        let mut message = model.to_message();
+        message
+            .players
+            .retain(|id, player| *id == player_id || self.visible(rules, player));
+        message
+            .projectiles
+            .retain(|_, projectile| self.visible(rules, projectile));
+        let mut result = Vec::new();
+        for event in events {
+            match event {
+                Event::Food(FoodEvent::Add(_)) | Event::Food(FoodEvent::OutOfView(_)) => {}
+                Event::Food(FoodEvent::Remove(id)) => {
+                    if self.visible_food.remove(&id) {
+                        result.push(event);
+                    }
+                }
+                Event::Chat {
+                    sender,
+                    ref channel,
+                    ..
+                } => {
+                    let delivered = match *channel {
+                        ChatChannel::All => true,
+                        ChatChannel::Team => {
+                            model.is_spectating(sender) == model.is_spectating(player_id)
+                        }
+                        ChatChannel::Whisper(to) => player_id == sender || player_id == to,
+                    };
+                    if delivered {
+                        result.push(event);
+                    }
+                }
+                _ => {
+                    if event.pos().is_none_or(|pos| self.in_range(rules, pos, 0.0)) {
+                        result.push(event);
+                    }
+                }
+            }
+        }
+        for food in &model.food {
+            let visible = self.visible(rules, food);
+            if visible && self.visible_food.insert(food.id) {
+                result.push(Event::Food(FoodEvent::Add(food.clone())));
+            } else if !visible && self.visible_food.remove(&food.id) {
+                result.push(Event::Food(FoodEvent::OutOfView(food.id)));
+            }
+        }
+        (message, result)
+    }
+}
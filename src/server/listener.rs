@@ -0,0 +1,123 @@
+use super::*;
+
+use net::Receiver;
+
+/// Connection of a client, telling where it comes from and able to hang up on it
+pub struct Connection {
+    sender: ws::Sender,
+    /// Address of the other end, which may be a proxy in front of the client
+    pub addr: Option<IpAddr>,
+}
+
+impl Connection {
+    pub fn send(&mut self, message: ServerMessage) {
+        net::Sender::send(&mut self.sender, message);
+    }
+
+    /// Hangs up once the messages sent before are delivered
+    pub fn close(&self) {
+        if let Err(e) = self.sender.close(ws::CloseCode::Policy) {
+            warn!("Failed to close connection: {}", e);
+        }
+    }
+}
+
+struct Handler {
+    app: ServerApp,
+    sender: ws::Sender,
+    client: Option<Client>,
+}
+
+impl ws::Handler for Handler {
+    fn on_open(&mut self, handshake: ws::Handshake) -> ws::Result<()> {
+        self.client = Some(self.app.connect(Connection {
+            sender: self.sender.clone(),
+            addr: handshake.peer_addr.map(|addr| addr.ip()),
+        }));
+        Ok(())
+    }
+    fn on_message(&mut self, message: ws::Message) -> ws::Result<()> {
+        let message: ClientMessage = match serde_json::from_slice(&message.into_data()) {
+            Ok(message) => message,
+            Err(e) => {
+                warn!("Failed to deserialize message from client: {}", e);
+                return Ok(());
+            }
+        };
+        trace!("Received message from client: {:?}", message);
+        self.client
+            .as_mut()
+            .expect("Received a message before handshake")
+            .handle(message);
+        Ok(())
+    }
+}
+
+struct Factory {
+    app: ServerApp,
+}
+
+impl ws::Factory for Factory {
+    type Handler = Handler;
+
+    fn connection_made(&mut self, sender: ws::Sender) -> Handler {
+        info!("New connection");
+        Handler {
+            app: self.app.clone(),
+            sender,
+            client: None,
+        }
+    }
+}
+
+/// Same as `net::Server`, but tells the app the address each client connects from
+pub struct Listener {
+    ws: ws::WebSocket<Factory>,
+}
+
+#[derive(Clone)]
+pub struct ServerHandle {
+    sender: ws::Sender,
+}
+
+impl ServerHandle {
+    pub fn shutdown(&self) {
+        self.sender.shutdown().expect("Failed to shutdown server");
+    }
+}
+
+impl Listener {
+    /// Fails if the address can not be listened on, like when the port is taken
+    pub fn new(app: ServerApp, addr: (&str, u16)) -> Result<Self, ConfigError> {
+        let ws = ws::WebSocket::new(Factory { app }).unwrap();
+        let ws = ws.bind(addr).map_err(|e| ConfigError::BadValue {
+            name: "--port".to_owned(),
+            value: addr.1.to_string(),
+            message: format!(
+                "failed to listen on {}: {}",
+                addr.0,
+                // Display of `ws::Error` is empty, the cause is in the kind
+                match e.kind {
+                    ws::ErrorKind::Io(error) => error.to_string(),
+                    kind => format!("{:?}", kind),
+                }
+            ),
+        })?;
+        Ok(Self { ws })
+    }
+    pub fn handle(&self) -> ServerHandle {
+        ServerHandle {
+            sender: self.ws.broadcaster(),
+        }
+    }
+    pub fn run(self) {
+        info!("Starting the server");
+        match self.ws.run() {
+            Ok(_) => info!("Server finished successfully"),
+            Err(e) => {
+                error!("Server shutdown with error: {}", e);
+                panic!("{:?}", e);
+            }
+        }
+    }
+}
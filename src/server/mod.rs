@@ -1,33 +1,471 @@
 use super::*;
 
+mod admin;
+mod ban;
+mod bench;
+mod chat;
+mod file;
+mod interest;
+mod listener;
 mod model;
+mod recorder;
+mod room;
+mod rules_file;
+mod session;
+mod snapshot;
 
+use admin::Console;
+use ban::{Ban, Bans};
+pub use bench::bench_encoding;
+use chat::Blocklist;
+use interest::Interest;
+use listener::{Connection, Listener, ServerHandle};
 use model::*;
+use recorder::Recorder;
+use room::{Room, Rooms, SharedRooms};
+use rules_file::RulesFile;
+use session::{Queued, Session, Sessions};
+
+use std::net::IpAddr;
+
+/// What the server is started with, besides the network options
+#[derive(Default)]
+pub struct ServerConfig {
+    /// Rules of the main room, given explicitly they override the ones in the restored snapshot
+    pub rules: Option<Rules>,
+    /// File the rules of the main room came from, changes to it are applied as the server runs
+    pub rules_path: Option<String>,
+    /// Rules of other public rooms by room id
+    pub rooms: HashMap<String, Rules>,
+    pub bot_personalities: Vec<BotPersonality>,
+    /// Record a replay of the main room to given file
+    pub record: Option<String>,
+    pub restore: Option<String>,
+    pub snapshot: Option<String>,
+    pub chat_blocklist: Option<String>,
+    /// Most clients playing at once, the rest wait in a queue
+    pub max_players: Option<usize>,
+    /// Secret that lets clients log in as admins
+    pub admin_key: Option<String>,
+    /// File to keep bans in
+    pub bans: Option<String>,
+    /// Read admin commands from stdin
+    pub console: bool,
+    /// Local port to take admin commands on
+    pub admin_port: Option<u16>,
+}
 
 struct Client {
-    player_id: Id,
-    name: Option<String>,
-    model: Arc<Mutex<Model>>,
-    events: std::sync::mpsc::Receiver<common_model::Event>,
-    sender: Box<dyn net::Sender<ServerMessage>>,
+    rooms: SharedRooms,
+    /// Room the client is in, the main room until it joins another one
+    room: Arc<Room>,
+    blocklist: Arc<Blocklist>,
+    admin_key: Option<Arc<str>>,
+    bans: Arc<Mutex<Bans>>,
+    /// Resume token of the session, set once the client is welcomed
+    token: Option<String>,
+    /// Moves into the session once the client is welcomed, not set for banned clients
+    sender: Option<Connection>,
 }
 
 impl Drop for Client {
     fn drop(&mut self) {
-        self.model.lock().unwrap().disconnect(self.player_id);
-        if let Some(name) = &self.name {
-            info!("{:?} disconnected", name);
+        if let Some(token) = &self.token {
+            let mut model = self.room.model.lock().unwrap();
+            self.room
+                .sessions
+                .lock()
+                .unwrap()
+                .suspend(&mut model, token);
+        }
+    }
+}
+
+impl Client {
+    fn hello(&mut self, hello: Hello) {
+        let mut sender = match self.sender.take() {
+            Some(sender) => sender,
+            None => return,
+        };
+        if let Some(reason) = hello.incompatibility() {
+            warn!("Rejected client: {}", reason);
+            sender.send(ServerMessage::Rejected(reason));
+            self.sender = Some(sender);
+            return;
+        }
+        let rooms = self.rooms.clone();
+        let mut rooms = rooms.lock().unwrap();
+        // Counting players locks the models of all rooms
+        let ticket = rooms.join_queue();
+        let mut model = self.room.model.lock().unwrap();
+        let player_id = model.new_player();
+        let mut session = Session::new(&model, player_id, sender);
+        if let Some(ticket) = ticket {
+            session.queued = Some(Queued {
+                ticket,
+                position: 0,
+            });
+            model.handle(player_id, ClientMessage::Spectate);
+        }
+        session.send(ServerMessage::Welcome(Hello::current()));
+        session.send(ServerMessage::RoomJoined(self.room.id.clone()));
+        session.send_state(&model, model.initial_events());
+        let mut sessions = self.room.sessions.lock().unwrap();
+        let token = sessions.start(session);
+        sessions
+            .active
+            .get_mut(&token)
+            .unwrap()
+            .send(ServerMessage::ResumeToken(token.clone()));
+        self.token = Some(token);
+    }
+
+    /// Moves the session to another room as a new player there, with rooms locked
+    fn move_to(&mut self, room: Arc<Room>) {
+        let token = self.token.as_ref().unwrap();
+        let mut model = self.room.model.lock().unwrap();
+        let mut sessions = self.room.sessions.lock().unwrap();
+        let session = match sessions.active.remove(token) {
+            Some(session) => session,
+            None => return,
+        };
+        model.disconnect(session.player_id);
+        drop(sessions);
+        drop(model);
+        self.enter(room, session);
+    }
+
+    /// Puts the session into the room as a new player, with rooms locked
+    fn enter(&mut self, room: Arc<Room>, mut session: Session) {
+        let token = self.token.clone().unwrap();
+        let mut model = room.model.lock().unwrap();
+        let player_id = model.new_player();
+        session.enter(&model, player_id);
+        if session.queued.is_some() {
+            model.handle(player_id, ClientMessage::Spectate);
+        }
+        if let Some(name) = &session.name {
+            info!("{:?} moved to room {:?}", name, room.id);
+            model.handle(player_id, ClientMessage::SetName(name.clone()));
+        }
+        session.send(ServerMessage::RoomJoined(room.id.clone()));
+        session.send_state(&model, model.initial_events());
+        room.sessions.lock().unwrap().active.insert(token, session);
+        drop(model);
+        self.room = room;
+    }
+
+    fn join_room(&mut self, id: &str) {
+        let rooms = self.rooms.clone();
+        let mut rooms = rooms.lock().unwrap();
+        match rooms.get_or_start(id) {
+            Some(room) if Arc::ptr_eq(&room, &self.room) => {}
+            Some(room) => self.move_to(room),
+            None => self.notify(format!("There is no room {:?}", id)),
+        }
+    }
+
+    /// Takes back the player of an old session, following it into its room
+    fn resume(&mut self, old_token: &str) {
+        let token = self.token.clone().unwrap();
+        let ban = self
+            .bans
+            .lock()
+            .unwrap()
+            .find(None, Some(old_token))
+            .cloned();
+        if let Some(ban) = ban {
+            warn!("Rejected banned client resuming an old session");
+            let mut model = self.room.model.lock().unwrap();
+            let mut sessions = self.room.sessions.lock().unwrap();
+            sessions.reject(&mut model, &token, &ban.message());
+            return;
+        }
+        {
+            let rooms = self.rooms.clone();
+            let rooms = rooms.lock().unwrap();
+            match rooms.find_session(old_token) {
+                Some(room) if Arc::ptr_eq(&room, &self.room) => {}
+                Some(room) => self.move_to(room),
+                None => {}
+            }
+        }
+        let mut model = self.room.model.lock().unwrap();
+        let mut sessions = self.room.sessions.lock().unwrap();
+        if sessions.resume(&mut model, &token, old_token) {
+            let session = sessions.active.get_mut(&token).unwrap();
+            session.send_state(&model, model.initial_events());
+        } else {
+            warn!("Unknown or expired resume token, keeping the new player");
+        }
+    }
+
+    fn create_room(&mut self, rules: Rules) {
+        if let Err(e) = room::check_rules(&rules) {
+            self.notify(format!("Can not create the room: {}", e));
+            return;
+        }
+        let rooms = self.rooms.clone();
+        let mut rooms = rooms.lock().unwrap();
+        let room = rooms.start_private(rules);
+        self.move_to(room.clone());
+        let token = self.token.as_ref().unwrap();
+        let sessions = room.sessions.lock().unwrap();
+        let host = match sessions.active.get(token) {
+            Some(session) => session.player_id,
+            None => return,
+        };
+        drop(sessions);
+        room.private.as_ref().unwrap().lock().unwrap().host = Some(host);
+        self.notify(format!(
+            "Created room {}, share the code to invite friends. \
+             You are the host: /start to start the match, /kick name, /rules name value",
+            room.id
+        ));
+    }
+
+    /// Kick, rule changes and match start, only for the host of a private room
+    fn host_command(&mut self, message: ClientMessage) {
+        let token = self.token.as_ref().unwrap();
+        let room = &self.room;
+        let mut model = room.model.lock().unwrap();
+        let mut private = match &room.private {
+            Some(private) => private.lock().unwrap(),
+            None => {
+                drop(model);
+                self.notify("Only private rooms have a host".to_owned());
+                return;
+            }
+        };
+        let mut sessions = room.sessions.lock().unwrap();
+        let player_id = match sessions.active.get(token) {
+            Some(session) => session.player_id,
+            None => return,
+        };
+        let host_present = private
+            .host
+            .is_some_and(|host| sessions.active.values().any(|s| s.player_id == host));
+        if !host_present {
+            private.host = Some(player_id);
+        }
+        let reply = if private.host != Some(player_id) {
+            "Only the host can do that".to_owned()
+        } else {
+            match message {
+                ClientMessage::Kick(id) if id == player_id => {
+                    "You can not kick yourself".to_owned()
+                }
+                ClientMessage::Kick(id) => {
+                    if sessions.kick(&mut model, id) {
+                        return;
+                    }
+                    "There is no such player".to_owned()
+                }
+                ClientMessage::SetRule { name, value } => match private.set_rule(&name, &value) {
+                    Ok(()) => {
+                        sessions.notify_all(&format!(
+                            "Host set {} to {}, from the next round",
+                            name, value
+                        ));
+                        return;
+                    }
+                    Err(e) => e,
+                },
+                ClientMessage::StartMatch => {
+                    model.restart(private.next_rules.clone());
+                    private.started = true;
+                    info!("Match started in room {:?}", room.id);
+                    sessions.notify_all("Match started, press R to spawn");
+                    return;
+                }
+                _ => unreachable!(),
+            }
+        };
+        if let Some(session) = sessions.active.get_mut(token) {
+            session.send(ServerMessage::Notice(reply));
+        }
+    }
+
+    /// Puts a spectator asking to play into the queue if there is no free slot,
+    /// returns whether it did
+    fn queue_to_spawn(&self, token: &str) -> bool {
+        let rooms = self.rooms.clone();
+        let mut rooms = rooms.lock().unwrap();
+        {
+            let model = self.room.model.lock().unwrap();
+            let sessions = self.room.sessions.lock().unwrap();
+            match sessions.active.get(token) {
+                Some(session)
+                    if session.queued.is_none()
+                        && !session.admin
+                        && model.is_spectating(session.player_id) => {}
+                _ => return false,
+            }
+        }
+        let ticket = match rooms.join_queue() {
+            Some(ticket) => ticket,
+            None => return false,
+        };
+        let mut sessions = self.room.sessions.lock().unwrap();
+        if let Some(session) = sessions.active.get_mut(token) {
+            session.queued = Some(Queued {
+                ticket,
+                position: 0,
+            });
+            session.send(ServerMessage::Notice(
+                "Server is full, you will spawn when your turn in the queue comes".to_owned(),
+            ));
+        }
+        true
+    }
+
+    fn notify(&self, text: String) {
+        let token = self.token.as_ref().unwrap();
+        if let Some(session) = self.room.sessions.lock().unwrap().active.get_mut(token) {
+            session.send(ServerMessage::Notice(text));
         }
     }
 }
 
 impl net::Receiver<ClientMessage> for Client {
     fn handle(&mut self, message: ClientMessage) {
-        let reply = match &message {
-            ClientMessage::Action(_) => true,
-            ClientMessage::Spawn => false,
+        let token = match &self.token {
+            Some(token) => token.clone(),
+            None => {
+                match message {
+                    ClientMessage::Hello(hello) => self.hello(hello),
+                    message => warn!("Got {:?} before hello, ignoring", message),
+                }
+                return;
+            }
+        };
+        match &message {
+            ClientMessage::Resume(old_token) => {
+                self.resume(old_token);
+                return;
+            }
+            ClientMessage::JoinRoom(id) => {
+                self.join_room(id);
+                return;
+            }
+            ClientMessage::CreateRoom(rules) => {
+                self.create_room(rules.clone());
+                return;
+            }
+            ClientMessage::Kick(_) | ClientMessage::SetRule { .. } | ClientMessage::StartMatch => {
+                self.host_command(message);
+                return;
+            }
+            ClientMessage::Spawn if self.queue_to_spawn(&token) => return,
+            ClientMessage::ListRooms => {
+                let list = self.rooms.lock().unwrap().list();
+                let mut sessions = self.room.sessions.lock().unwrap();
+                if let Some(session) = sessions.active.get_mut(&token) {
+                    session.send(ServerMessage::Rooms(list));
+                }
+                return;
+            }
+            _ => {}
+        }
+        let room = self.room.clone();
+        let mut model = room.model.lock().unwrap();
+        if let ClientMessage::Spawn = message {
+            if room.waiting() {
+                drop(model);
+                self.notify("Waiting for the host to start the match".to_owned());
+                return;
+            }
+        }
+        let mut sessions = room.sessions.lock().unwrap();
+        let session = match sessions.active.get_mut(&token) {
+            Some(session) => session,
+            None => {
+                if let Some(session) = sessions.kicked.remove(&token) {
+                    drop(sessions);
+                    drop(model);
+                    let rooms = self.rooms.clone();
+                    let mut rooms = rooms.lock().unwrap();
+                    let main = rooms.get_or_start(Rooms::MAIN).unwrap();
+                    self.enter(main, session);
+                }
+                // Otherwise taken over by a resumed connection of the same client
+                return;
+            }
+        };
+        let player_id = session.player_id;
+        session.input(&model, &message);
+        match &message {
+            ClientMessage::Action { seq, ack, .. } => {
+                session.delta.ack(*ack);
+                if session.last_input.is_some_and(|last| *seq <= last) {
+                    return;
+                }
+                session.last_input = Some(*seq);
+            }
+            ClientMessage::Spawn if session.queued.is_some() => {
+                session.send(ServerMessage::Notice(
+                    "Server is full, wait for your turn in the queue".to_owned(),
+                ));
+                return;
+            }
+            ClientMessage::Spectate if session.queued.is_some() => {
+                // Only wants to watch, so the place in the queue goes to others
+                session.queued = None;
+                session.send(ServerMessage::Queue(None));
+            }
+            ClientMessage::Spawn | ClientMessage::Spectate => {}
+            ClientMessage::AdminLogin(key) => {
+                if self.admin_key.as_deref() != Some(key.as_str()) {
+                    warn!("Wrong admin key from {:?}", session.name);
+                    session.send(ServerMessage::Notice("Wrong admin key".to_owned()));
+                    return;
+                }
+                info!("{:?} logged in as admin", session.name);
+                session.admin = true;
+                if session.queued.take().is_some() {
+                    session.send(ServerMessage::Queue(None));
+                }
+                session.send(ServerMessage::Notice("Logged in as admin".to_owned()));
+                return;
+            }
+            ClientMessage::Follow(id) => {
+                session.interest.follow = *id;
+                session.interest.camera = None;
+            }
+            ClientMessage::Look(pos) => session.interest.camera = Some(*pos),
+            ClientMessage::SetEncoding(encoding) => session.delta.encoding = *encoding,
+            ClientMessage::Hello(_) => {
+                warn!("Got repeated hello, ignoring");
+                return;
+            }
+            ClientMessage::Resume(_)
+            | ClientMessage::JoinRoom(_)
+            | ClientMessage::ListRooms
+            | ClientMessage::CreateRoom(_)
+            | ClientMessage::Kick(_)
+            | ClientMessage::SetRule { .. }
+            | ClientMessage::StartMatch => unreachable!(),
+            ClientMessage::Chat(_) if session.muted => {
+                session.send(ServerMessage::Notice("You are muted".to_owned()));
+                return;
+            }
+            ClientMessage::Chat(text) => {
+                if let Err(e) = chat::handle(
+                    &mut model,
+                    &mut session.chat_limit,
+                    &self.blocklist,
+                    player_id,
+                    text,
+                ) {
+                    session.send(ServerMessage::Notice(e));
+                }
+                return;
+            }
             ClientMessage::SetName(name) => {
-                self.name = Some(name.clone());
+                if session.name.as_ref() == Some(name) {
+                    return;
+                }
+                session.name = Some(name.clone());
                 info!("{:?} joined the game", name);
                 if let Ok(cmd) = std::env::var("NEW_PLAYER_CMD") {
                     std::process::Command::new(cmd)
@@ -35,88 +473,207 @@ impl net::Receiver<ClientMessage> for Client {
                         .spawn()
                         .expect("Failed to run NEW_PLAYER_CMD");
                 }
-                false
             }
-        };
-        let mut model = self.model.lock().unwrap();
-        model.handle(self.player_id, message);
-        if reply {
-            self.sender.send(ServerMessage {
-                client_player_id: self.player_id,
-                model: model.to_message(),
-                events: self.events.try_iter().collect(),
-            });
         }
+        drop(sessions);
+        if let Some(recorder) = &room.recorder {
+            recorder.record_input(model.current_tick, player_id, &message);
+        }
+        model.handle(player_id, message);
     }
 }
+#[derive(Clone)]
 struct ServerApp {
-    model: Arc<Mutex<Model>>,
+    rooms: SharedRooms,
+    main: Arc<Room>,
+    blocklist: Arc<Blocklist>,
+    admin_key: Option<Arc<str>>,
+    bans: Arc<Mutex<Bans>>,
 }
-impl net::server::App for ServerApp {
-    type Client = Client;
-    type ServerMessage = ServerMessage;
-    type ClientMessage = ClientMessage;
-    fn connect(&mut self, mut sender: Box<dyn net::Sender<ServerMessage>>) -> Client {
-        let (player_id, events) = {
-            let mut model = self.model.lock().unwrap();
-            let player_id = model.new_player();
-            sender.send(ServerMessage {
-                client_player_id: player_id,
-                model: model.to_message(),
-                events: model.initial_events(),
-            });
-            (player_id, model.events.subscribe())
+impl ServerApp {
+    fn connect(&self, mut connection: Connection) -> Client {
+        let mut client = Client {
+            rooms: self.rooms.clone(),
+            room: self.main.clone(),
+            blocklist: self.blocklist.clone(),
+            admin_key: self.admin_key.clone(),
+            bans: self.bans.clone(),
+            token: None,
+            sender: None,
         };
-        Client {
-            model: self.model.clone(),
-            name: None,
-            player_id,
-            sender,
-            events,
+        let bans = self.bans.lock().unwrap();
+        match bans.find(connection.addr, None) {
+            Some(ban) => {
+                warn!("Rejected banned client from {:?}", connection.addr);
+                connection.send(ServerMessage::Rejected(ban.message()));
+                connection.close();
+            }
+            None => client.sender = Some(connection),
         }
+        client
     }
 }
 
 pub struct Server {
-    model: Arc<Mutex<Model>>,
-    server: net::Server<ServerApp>,
+    rooms: SharedRooms,
+    main: Arc<Room>,
+    snapshot_path: Option<String>,
+    rules_file: Option<RulesFile>,
+    bans: Arc<Mutex<Bans>>,
+    console: Console,
+    read_stdin: bool,
+    admin_port: Option<u16>,
+    server: Listener,
 }
 
 impl Server {
     const TICKS_PER_SECOND: f64 = Model::TICKS_PER_SECOND;
-    pub fn new(net_opts: &NetOpts, rules: Rules) -> Self {
-        let model = Arc::new(Mutex::new(Model::new(rules)));
-        Self {
-            model: model.clone(),
-            server: net::Server::new(
+    /// How often the world snapshot is saved, in seconds
+    const SNAPSHOT_INTERVAL: f64 = 60.0;
+    /// How often the ban and rules files are checked for changes, in seconds
+    const WATCH_INTERVAL: f64 = 1.0;
+    pub fn new(net_opts: &NetOpts, config: ServerConfig) -> Result<Self, ConfigError> {
+        let (model, sessions) = match &config.restore {
+            Some(path) => {
+                let snapshot = snapshot::load(path)?;
+                let sessions = snapshot.sessions.clone();
+                let mut model = Model::restore(snapshot, config.bot_personalities.clone());
+                if let Some(rules) = config.rules {
+                    model.rules = rules;
+                }
+                (model, sessions)
+            }
+            None => (
+                Model::new(
+                    config.rules.unwrap_or_default(),
+                    config.bot_personalities.clone(),
+                ),
+                HashMap::new(),
+            ),
+        };
+        let recorder = config
+            .record
+            .as_ref()
+            .map(|path| {
+                Recorder::new(path, &model).map_err(|e| ConfigError::Invalid {
+                    path: path.clone(),
+                    message: format!("failed to start recording replay: {}", e),
+                })
+            })
+            .transpose()?;
+        let blocklist = match &config.chat_blocklist {
+            Some(path) => Blocklist::load(path)?,
+            None => Blocklist::default(),
+        };
+        let main = Arc::new(Room::new(Rooms::MAIN, model, recorder));
+        {
+            let mut model = main.model.lock().unwrap();
+            main.sessions.lock().unwrap().restore(&mut model, sessions);
+        }
+        let rooms = Arc::new(Mutex::new(Rooms::new(
+            main.clone(),
+            config.rooms,
+            config.bot_personalities,
+            config.max_players,
+        )));
+        let bans = match &config.bans {
+            Some(path) => Bans::load(path)?,
+            None => Bans::default(),
+        };
+        let bans = Arc::new(Mutex::new(bans));
+        Ok(Self {
+            rooms: rooms.clone(),
+            main: main.clone(),
+            snapshot_path: config.snapshot.clone(),
+            rules_file: config.rules_path.as_deref().map(RulesFile::new),
+            bans: bans.clone(),
+            console: Console::new(rooms.clone(), bans.clone(), config.snapshot),
+            read_stdin: config.console,
+            admin_port: config.admin_port,
+            server: Listener::new(
                 ServerApp {
-                    model: model.clone(),
+                    rooms,
+                    main,
+                    blocklist: Arc::new(blocklist),
+                    admin_key: config.admin_key.map(|key| key.into()),
+                    bans,
                 },
                 (net_opts.host.as_str(), net_opts.port),
-            ),
-        }
+            )?,
+        })
     }
-    pub fn handle(&self) -> net::ServerHandle {
+    pub fn handle(&self) -> ServerHandle {
         self.server.handle()
     }
-    pub fn run(self) {
+    pub fn run(mut self) {
         let running = Arc::new(std::sync::atomic::AtomicBool::new(true));
         let server_thread = std::thread::spawn({
-            let model = self.model;
+            let rooms = self.rooms.clone();
+            let main = self.main.clone();
+            let snapshot_path = self.snapshot_path.clone();
+            let bans = self.bans.clone();
+            let mut rules_file = self.rules_file.take();
             let running = running.clone();
             move || {
+                let mut watch_timer = Timer::new();
                 while running.load(std::sync::atomic::Ordering::Relaxed) {
                     // TODO: smoother TPS
                     std::thread::sleep(std::time::Duration::from_millis(
                         (1000.0 / Self::TICKS_PER_SECOND) as u64,
                     ));
-                    let mut model = model.lock().unwrap();
-                    model.tick();
+                    let running_rooms: Vec<Arc<Room>> =
+                        rooms.lock().unwrap().running.values().cloned().collect();
+                    for room in running_rooms {
+                        room.tick();
+                    }
+                    let mut rooms = rooms.lock().unwrap();
+                    rooms.close_empty();
+                    rooms.update_queue();
+                    let running_rooms: Vec<Arc<Room>> = rooms.running.values().cloned().collect();
+                    drop(rooms);
+                    if watch_timer.elapsed() > Self::WATCH_INTERVAL {
+                        watch_timer = Timer::new();
+                        if let Some(rules) = rules_file.as_mut().and_then(|file| file.check()) {
+                            let mut model = main.model.lock().unwrap();
+                            let changes = rules_file::diff(&model.rules, &rules);
+                            if !changes.is_empty() {
+                                info!("Rules changed:\n{}", changes.join("\n"));
+                                model.set_rules(rules);
+                                main.sessions
+                                    .lock()
+                                    .unwrap()
+                                    .notify_all(&format!("Rules changed: {}", changes.join(", ")));
+                            }
+                        }
+                        if bans.lock().unwrap().reload_if_changed() {
+                            for room in running_rooms {
+                                let mut model = room.model.lock().unwrap();
+                                let mut sessions = room.sessions.lock().unwrap();
+                                sessions.reject_banned(&mut model, &bans.lock().unwrap());
+                            }
+                        }
+                    }
+                    if let Some(path) = &snapshot_path {
+                        let interval = (Self::SNAPSHOT_INTERVAL * Self::TICKS_PER_SECOND) as u64;
+                        let current_tick = main.model.lock().unwrap().current_tick;
+                        if current_tick.is_multiple_of(interval) {
+                            snapshot::save(path, &main.snapshot());
+                        }
+                    }
                 }
             }
         });
+        if self.read_stdin {
+            admin::read_stdin(self.console.clone());
+        }
+        if let Some(port) = self.admin_port {
+            config::or_exit(admin::listen(self.console.clone(), port));
+        }
         self.server.run();
         running.store(false, std::sync::atomic::Ordering::Relaxed);
         server_thread.join().expect("Failed to join server thread");
+        if let Some(path) = &self.snapshot_path {
+            snapshot::save(path, &self.main.snapshot());
+        }
     }
 }
@@ -1,3 +1,3 @@
 use super::*;
 
-pub use common_model::{Model, ModelMessage};
+pub use common_model::{DeltaEncoder, Model, ModelMessage};
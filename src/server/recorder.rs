@@ -0,0 +1,98 @@
+use super::*;
+
+use common_model::{ChatChannel, DeltaEncoder, Event};
+
+/// Writes a replay of the match as it goes
+pub struct Recorder {
+    writer: Mutex<std::io::BufWriter<std::fs::File>>,
+    events: Mutex<std::sync::mpsc::Receiver<Event>>,
+    /// Encodes frames relative to the previous one, the way updates are sent to clients
+    encoder: Mutex<DeltaEncoder>,
+    frames: Mutex<u64>,
+}
+
+impl Recorder {
+    /// Frames are only recorded every this many ticks, clients interpolate in between
+    const FRAME_INTERVAL: u64 = 3;
+    /// Every this many frames the whole world is recorded, so that seeking does not start over
+    const KEYFRAME_INTERVAL: u64 = 100;
+
+    pub fn new(path: &str, model: &Model) -> std::io::Result<Self> {
+        let mut writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+        let header = ReplayHeader {
+            version: ReplayHeader::VERSION,
+            build: env!("CARGO_PKG_VERSION").to_owned(),
+            rules: model.rules.clone(),
+            seed: model.seed,
+        };
+        serde_json::to_writer(&mut writer, &header)?;
+        writer.write_all(b"\n")?;
+        let mut encoder = DeltaEncoder::new();
+        encoder.encoding = Encoding::Compact;
+        let recorder = Self {
+            writer: Mutex::new(writer),
+            events: Mutex::new(model.events.subscribe()),
+            encoder: Mutex::new(encoder),
+            frames: Mutex::new(0),
+        };
+        recorder.write_frame(model, Vec::new());
+        info!("Recording replay to {:?}", path);
+        Ok(recorder)
+    }
+
+    pub fn record_input(&self, tick: u64, player_id: Id, message: &ClientMessage) {
+        self.write(&ReplayEntry::Input {
+            tick,
+            player_id,
+            message: message.clone(),
+        });
+    }
+
+    pub fn record_tick(&self, model: &Model) {
+        if !model.current_tick.is_multiple_of(Self::FRAME_INTERVAL) {
+            return;
+        }
+        // Whispers and team chat are not for everyone watching the replay
+        let events = self
+            .events
+            .lock()
+            .unwrap()
+            .try_iter()
+            .filter(|event| match event {
+                Event::Chat { channel, .. } => matches!(channel, ChatChannel::All),
+                _ => true,
+            })
+            .collect();
+        self.write_frame(model, events);
+        if let Err(e) = self.writer.lock().unwrap().flush() {
+            error!("Failed to flush replay: {}", e);
+        }
+    }
+
+    fn write_frame(&self, model: &Model, events: Vec<Event>) {
+        let mut frames = self.frames.lock().unwrap();
+        let mut encoder = self.encoder.lock().unwrap();
+        let keyframe = frames.is_multiple_of(Self::KEYFRAME_INTERVAL);
+        // Encoder numbers snapshots in order, so previous frame is the one before
+        encoder.ack(if keyframe { None } else { Some(*frames - 1) });
+        let entry = ReplayEntry::Frame {
+            tick: model.current_tick,
+            keyframe,
+            update: encoder.encode(model.to_message()),
+            state: if keyframe {
+                model.initial_events()
+            } else {
+                Vec::new()
+            },
+            events,
+        };
+        *frames += 1;
+        self.write(&entry);
+    }
+
+    fn write(&self, entry: &ReplayEntry) {
+        if let Err(e) = bincode::serialize_into(&mut *self.writer.lock().unwrap(), entry) {
+            error!("Failed to record replay: {}", e);
+        }
+    }
+}
@@ -0,0 +1,312 @@
+use super::*;
+
+use common_model::{RoomInfo, WorldSnapshot};
+
+/// Room made by a player, joined with its invite code
+pub struct Private {
+    /// Player that made the room, or the first to use host powers after they left
+    pub host: Option<Id>,
+    /// Rules the next round starts with
+    pub next_rules: Rules,
+    /// Whether the host started the match, nobody spawns before that
+    pub started: bool,
+}
+
+impl Private {
+    /// Changes a field of the next rules
+    pub fn set_rule(&mut self, name: &str, value: &str) -> Result<(), String> {
+        self.next_rules = set_rule(&self.next_rules, name, value)?;
+        Ok(())
+    }
+}
+
+/// Rules with one field changed, kept within what players may set
+pub fn set_rule(rules: &Rules, name: &str, value: &str) -> Result<Rules, String> {
+    let rules = change_rule(rules, name, value)?;
+    check_rules(&rules)?;
+    Ok(rules)
+}
+
+/// Rules with one field changed, taking field names with or without underscores
+pub fn change_rule(rules: &Rules, name: &str, value: &str) -> Result<Rules, String> {
+    let mut rules = serde_json::to_value(rules).unwrap();
+    let fields = rules.as_object_mut().unwrap();
+    let key = fields
+        .keys()
+        .find(|key| key.replace('_', "") == name.replace('_', "").to_lowercase())
+        .cloned()
+        .ok_or_else(|| format!("There is no rule {:?}", name))?;
+    fields.insert(
+        key,
+        serde_json::from_str(value).unwrap_or_else(|_| value.into()),
+    );
+    serde_json::from_value(rules).map_err(|e| e.to_string())
+}
+
+/// Keeps rules made up by players within what the server can afford
+pub fn check_rules(rules: &Rules) -> Result<(), String> {
+    rules.check()?;
+    if !(10.0..=1000.0).contains(&rules.world_size) {
+        return Err("World size must be from 10 to 1000".to_owned());
+    }
+    if !(1.0..=60.0).contains(&rules.snapshot_rate) {
+        return Err("Snapshot rate must be from 1 to 60".to_owned());
+    }
+    if rules.interest_radius <= 0.0 {
+        return Err("Interest radius must be positive".to_owned());
+    }
+    if rules.target_population > 30 {
+        return Err("Target population can not be over 30".to_owned());
+    }
+    Ok(())
+}
+
+/// Independent world with its own clients
+pub struct Room {
+    pub id: String,
+    pub model: Mutex<Model>,
+    pub sessions: Mutex<Sessions>,
+    pub recorder: Option<Recorder>,
+    /// Set for rooms made by players, locked after the model and before the sessions
+    pub private: Option<Mutex<Private>>,
+}
+
+impl Room {
+    pub fn new(id: &str, model: Model, recorder: Option<Recorder>) -> Self {
+        Self {
+            id: id.to_owned(),
+            model: Mutex::new(model),
+            sessions: default(),
+            recorder,
+            private: None,
+        }
+    }
+
+    /// World along with the tokens its clients can resume with
+    pub fn snapshot(&self) -> WorldSnapshot {
+        let model = self.model.lock().unwrap();
+        let mut world = model.snapshot();
+        world.sessions = self.sessions.lock().unwrap().snapshot();
+        world
+    }
+
+    /// Whether players have to wait for the host to start the match
+    pub fn waiting(&self) -> bool {
+        self.private
+            .as_ref()
+            .is_some_and(|private| !private.lock().unwrap().started)
+    }
+
+    /// Advances the world, pushing updates to clients on ticks given by `snapshot_rate`
+    pub fn tick(&self) {
+        let mut model = self.model.lock().unwrap();
+        model.tick();
+        if let Some(recorder) = &self.recorder {
+            recorder.record_tick(&model);
+        }
+        let ticks_per_update =
+            (Model::TICKS_PER_SECOND / model.rules.snapshot_rate as f64).round() as u64;
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.expire(&mut model);
+        sessions.check_idle(&mut model);
+        if model.current_tick.is_multiple_of(ticks_per_update.max(1)) {
+            for session in sessions.active.values_mut() {
+                session.send_update(&model);
+            }
+        }
+    }
+}
+
+/// Rooms of the server, always locked before the model of any room
+pub struct Rooms {
+    /// Rules of public rooms other than the main one, started once somebody joins
+    presets: HashMap<String, Rules>,
+    bot_personalities: Vec<BotPersonality>,
+    pub running: HashMap<String, Arc<Room>>,
+    /// Most clients playing at once in all rooms, others wait in the queue
+    max_players: Option<usize>,
+    /// Ticket of the next client to join
+    next_ticket: u64,
+}
+
+/// Rooms of the server, shared by client handlers and the tick thread
+pub type SharedRooms = Arc<Mutex<Rooms>>;
+
+impl Rooms {
+    /// Room where clients land after connecting, it is never torn down
+    pub const MAIN: &'static str = "main";
+
+    pub fn new(
+        main: Arc<Room>,
+        presets: HashMap<String, Rules>,
+        bot_personalities: Vec<BotPersonality>,
+        max_players: Option<usize>,
+    ) -> Self {
+        Self {
+            presets,
+            bot_personalities,
+            running: std::iter::once((main.id.clone(), main)).collect(),
+            max_players,
+            next_ticket: 0,
+        }
+    }
+
+    /// Locks models of all rooms, so none may be locked by the caller
+    fn admitted(&self) -> usize {
+        self.running
+            .values()
+            .map(|room| {
+                let model = room.model.lock().unwrap();
+                room.sessions.lock().unwrap().admitted(&model)
+            })
+            .sum()
+    }
+
+    /// Queue ticket for a client wanting to play, or `None` if it can play right away
+    pub fn join_queue(&mut self) -> Option<u64> {
+        let max_players = self.max_players?;
+        let anyone_queued = self.running.values().any(|room| {
+            let sessions = room.sessions.lock().unwrap();
+            sessions
+                .active
+                .values()
+                .any(|session| session.queued.is_some())
+        });
+        if !anyone_queued && self.admitted() < max_players {
+            return None;
+        }
+        self.next_ticket += 1;
+        Some(self.next_ticket)
+    }
+
+    /// Lets queued clients in as slots get free and spawns them, telling the rest their new positions
+    pub fn update_queue(&mut self) {
+        let max_players = match self.max_players {
+            Some(max_players) => max_players,
+            None => return,
+        };
+        let mut admitted = self.admitted();
+        let mut queued = Vec::new();
+        for room in self.running.values() {
+            for (token, session) in &room.sessions.lock().unwrap().active {
+                if let Some(queued_session) = session.queued {
+                    queued.push((queued_session.ticket, room.clone(), token.clone()));
+                }
+            }
+        }
+        queued.sort_by_key(|(ticket, _, _)| *ticket);
+        let mut position = 0;
+        for (_, room, token) in queued {
+            let waiting = room.waiting();
+            let mut model = room.model.lock().unwrap();
+            let mut sessions = room.sessions.lock().unwrap();
+            // Client may have left or stopped waiting since
+            let session = match sessions.active.get_mut(&token) {
+                Some(session) if session.queued.is_some() => session,
+                _ => continue,
+            };
+            let queued_session = session.queued.as_mut().unwrap();
+            if admitted < max_players {
+                admitted += 1;
+                session.queued = None;
+                session.send(ServerMessage::Queue(None));
+                if let Some(name) = &session.name {
+                    info!("{:?} got a slot", name);
+                }
+                if waiting {
+                    session.send(ServerMessage::Notice(
+                        "Your turn to play, once the host starts the match".to_owned(),
+                    ));
+                } else {
+                    session.send(ServerMessage::Notice("Your turn to play".to_owned()));
+                    model.handle(session.player_id, ClientMessage::Spawn);
+                }
+                continue;
+            }
+            position += 1;
+            if queued_session.position != position {
+                queued_session.position = position;
+                session.send(ServerMessage::Queue(Some(position)));
+            }
+        }
+    }
+
+    /// Public rooms, whether started or not, main room first
+    pub fn list(&self) -> Vec<RoomInfo> {
+        let mut ids: Vec<&String> = self.presets.keys().collect();
+        ids.sort();
+        std::iter::once(Self::MAIN)
+            .chain(ids.into_iter().map(|id| id.as_str()))
+            .map(|id| RoomInfo {
+                id: id.to_owned(),
+                players: self
+                    .running
+                    .get(id)
+                    .map_or(0, |room| room.sessions.lock().unwrap().active.len()),
+            })
+            .collect()
+    }
+
+    /// Running room with given id, starting it from its preset if needed
+    pub fn get_or_start(&mut self, id: &str) -> Option<Arc<Room>> {
+        if let Some(room) = self.running.get(id) {
+            return Some(room.clone());
+        }
+        let rules = self.presets.get(id)?.clone();
+        info!("Starting room {:?}", id);
+        let room = Arc::new(Room::new(
+            id,
+            Model::new(rules, self.bot_personalities.clone()),
+            None,
+        ));
+        self.running.insert(id.to_owned(), room.clone());
+        Some(room)
+    }
+
+    /// Starts a private room with given rules, not shown in the room list
+    pub fn start_private(&mut self, rules: Rules) -> Arc<Room> {
+        const CODE_CHARS: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+        const CODE_LENGTH: usize = 6;
+        let id = loop {
+            let code: String = (0..CODE_LENGTH)
+                .map(|_| CODE_CHARS[global_rng().gen_range(0, CODE_CHARS.len())] as char)
+                .collect();
+            if !self.running.contains_key(&code) && !self.presets.contains_key(&code) {
+                break code;
+            }
+        };
+        info!("Starting private room {:?}", id);
+        let mut room = Room::new(
+            &id,
+            Model::new(rules.clone(), self.bot_personalities.clone()),
+            None,
+        );
+        room.private = Some(Mutex::new(Private {
+            host: None,
+            next_rules: rules,
+            started: false,
+        }));
+        let room = Arc::new(room);
+        self.running.insert(id, room.clone());
+        room
+    }
+
+    /// Room with a connected or suspended session with given token
+    pub fn find_session(&self, token: &str) -> Option<Arc<Room>> {
+        self.running
+            .values()
+            .find(|room| room.sessions.lock().unwrap().contains(token))
+            .cloned()
+    }
+
+    /// Tears down rooms with nobody left but bots
+    pub fn close_empty(&mut self) {
+        self.running.retain(|id, room| {
+            if id == Self::MAIN || !room.sessions.lock().unwrap().is_empty() {
+                return true;
+            }
+            info!("Closing empty room {:?}", id);
+            false
+        });
+    }
+}
@@ -0,0 +1,46 @@
+use super::*;
+
+use std::time::SystemTime;
+
+/// Rules file of the main room, read again whenever it changes
+pub struct RulesFile {
+    path: String,
+    /// Modification time of the file when it was last read
+    modified: Option<SystemTime>,
+}
+
+impl RulesFile {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_owned(),
+            modified: file::modified(path),
+        }
+    }
+
+    /// New rules if the file changed since the last check, a broken file is reported once
+    pub fn check(&mut self) -> Option<Rules> {
+        let modified = file::modified(&self.path);
+        if modified.is_none() || modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+        match config::load_rules(&self.path) {
+            Ok(rules) => Some(rules),
+            Err(e) => {
+                error!("Failed to reload rules, keeping the old ones: {}", e);
+                None
+            }
+        }
+    }
+}
+
+/// Fields that differ between the rules, as `name: old -> new`
+pub fn diff(old: &Rules, new: &Rules) -> Vec<String> {
+    let old = serde_json::to_value(old).unwrap();
+    let new = serde_json::to_value(new).unwrap();
+    let (old, new) = (old.as_object().unwrap(), new.as_object().unwrap());
+    new.iter()
+        .filter(|(name, value)| old.get(*name) != Some(value))
+        .map(|(name, value)| format!("{}: {} -> {}", name, old[name], value))
+        .collect()
+}
@@ -0,0 +1,404 @@
+use super::*;
+
+use common_model::SessionSnapshot;
+
+/// Place of a client waiting for a free player slot
+#[derive(Clone, Copy)]
+pub struct Queued {
+    /// Order of joining, lower tickets get in first
+    pub ticket: u64,
+    /// Position last told to the client
+    pub position: usize,
+}
+
+/// Client that said a compatible hello and got a player in the world
+pub struct Session {
+    pub player_id: Id,
+    pub name: Option<String>,
+    pub delta: DeltaEncoder,
+    pub interest: Interest,
+    events: std::sync::mpsc::Receiver<common_model::Event>,
+    /// Sequence number of the latest input applied
+    pub last_input: Option<u64>,
+    pub chat_limit: chat::RateLimit,
+    /// Set while waiting for a player slot, spectating in the meantime
+    pub queued: Option<Queued>,
+    /// Logged in with the admin key
+    pub admin: bool,
+    /// Muted by an admin, chat messages of the client are dropped
+    pub muted: bool,
+    /// Model time of the last input that changed anything
+    active_time: f32,
+    /// Aim of the latest action, moving the aim counts as activity
+    aim: Vec2<f32>,
+    /// How far the client got in being warned, despawned and disconnected for idling
+    idle_stage: usize,
+    connection: Connection,
+}
+
+impl Session {
+    pub fn new(model: &Model, player_id: Id, connection: Connection) -> Self {
+        Self {
+            player_id,
+            name: None,
+            delta: DeltaEncoder::new(),
+            interest: Interest::new(),
+            events: model.events.subscribe(),
+            last_input: None,
+            chat_limit: chat::RateLimit::new(),
+            queued: None,
+            admin: false,
+            muted: false,
+            active_time: model.current_time,
+            aim: vec2(0.0, 0.0),
+            idle_stage: 0,
+            connection,
+        }
+    }
+
+    /// Starts over in another world as the given player, keeping the connection
+    pub fn enter(&mut self, model: &Model, player_id: Id) {
+        let encoding = self.delta.encoding;
+        self.player_id = player_id;
+        self.delta = DeltaEncoder::new();
+        self.delta.encoding = encoding;
+        self.interest = Interest::new();
+        self.events = model.events.subscribe();
+        self.active_time = model.current_time;
+        self.idle_stage = 0;
+    }
+
+    /// Notes an input from the client, anything but standing still with the same aim counts as activity
+    pub fn input(&mut self, model: &Model, message: &ClientMessage) {
+        match message {
+            ClientMessage::Action { action, .. } => {
+                let aimed = action.aim != self.aim;
+                self.aim = action.aim;
+                if action.target_vel == vec2(0.0, 0.0) && !action.shoot && !aimed {
+                    return;
+                }
+            }
+            ClientMessage::Spawn | ClientMessage::SetName(_) | ClientMessage::Chat(_) => {}
+            _ => return,
+        }
+        self.active_time = model.current_time;
+        self.idle_stage = 0;
+    }
+
+    /// Address the client connects from
+    pub fn addr(&self) -> Option<IpAddr> {
+        self.connection.addr
+    }
+
+    pub fn send(&mut self, message: ServerMessage) {
+        self.connection.send(message);
+    }
+
+    /// Pushes the world as seen by the client, with given events
+    pub fn send_state(
+        &mut self,
+        model: &Model,
+        events: impl IntoIterator<Item = common_model::Event>,
+    ) {
+        let (message, events) = self.interest.filter(model, self.player_id, events);
+        self.connection.send(ServerMessage::Update(StateUpdate {
+            client_player_id: self.player_id,
+            model: self.delta.encode(message),
+            events,
+            last_input: self.last_input,
+        }));
+    }
+
+    /// Pushes the world as seen by the client, with events fired since previous update
+    pub fn send_update(&mut self, model: &Model) {
+        let events: Vec<_> = self.events.try_iter().collect();
+        self.send_state(model, events);
+    }
+}
+
+/// Player of a disconnected client, left in the world for a while
+struct Suspended {
+    player_id: Id,
+    name: Option<String>,
+    queued: Option<Queued>,
+    admin: bool,
+    muted: bool,
+    /// Model time after which the player is removed
+    deadline: f32,
+}
+
+/// Sessions of all clients by their resume tokens, always locked after the model
+#[derive(Default)]
+pub struct Sessions {
+    /// Sessions of connected clients
+    pub active: HashMap<String, Session>,
+    /// Players of disconnected clients
+    suspended: HashMap<String, Suspended>,
+    /// Sessions kicked by the host, waiting for their clients to take them to the main room
+    pub kicked: HashMap<String, Session>,
+}
+
+impl Sessions {
+    /// How long a disconnected player waits for its client to come back, in seconds
+    const RESUME_GRACE: f32 = 30.0;
+
+    /// Whether there is nobody connected or waiting to resume
+    pub fn is_empty(&self) -> bool {
+        self.active.is_empty() && self.suspended.is_empty()
+    }
+
+    /// Sends a notice to every connected client
+    pub fn notify_all(&mut self, text: &str) {
+        for session in self.active.values_mut() {
+            session.send(ServerMessage::Notice(text.to_owned()));
+        }
+    }
+
+    /// Token of the connected session playing as given player
+    pub fn find(&self, player_id: Id) -> Option<String> {
+        self.active
+            .iter()
+            .find(|(_, session)| session.player_id == player_id)
+            .map(|(token, _)| token.clone())
+    }
+
+    /// Connected session playing as given player
+    pub fn get_player_mut(&mut self, player_id: Id) -> Option<&mut Session> {
+        self.active
+            .values_mut()
+            .find(|session| session.player_id == player_id)
+    }
+
+    /// Removes the player from the world, leaving the session for its client to move
+    pub fn kick(&mut self, model: &mut Model, player_id: Id) -> bool {
+        let token = match self.find(player_id) {
+            Some(token) => token,
+            None => return false,
+        };
+        let mut session = self.active.remove(&token).unwrap();
+        model.disconnect(player_id);
+        if let Some(name) = &session.name {
+            info!("{:?} was kicked", name);
+        }
+        session.send(ServerMessage::Notice(
+            "You were kicked from the room by the host".to_owned(),
+        ));
+        self.kicked.insert(token, session);
+        true
+    }
+
+    /// Connected or suspended clients holding a player slot, spectators do not
+    pub fn admitted(&self, model: &Model) -> usize {
+        let active = self
+            .active
+            .values()
+            .map(|session| (session.player_id, session.queued));
+        let suspended = self
+            .suspended
+            .values()
+            .map(|suspended| (suspended.player_id, suspended.queued));
+        active
+            .chain(suspended)
+            .filter(|(player_id, queued)| queued.is_none() && !model.is_spectating(*player_id))
+            .count()
+    }
+
+    /// Whether a connected or suspended session has given token
+    pub fn contains(&self, token: &str) -> bool {
+        self.active.contains_key(token) || self.suspended.contains_key(token)
+    }
+
+    /// Starts a session, returning its token
+    pub fn start(&mut self, session: Session) -> String {
+        let token = format!("{:032x}", global_rng().gen::<u128>());
+        self.active.insert(token.clone(), session);
+        token
+    }
+
+    /// Freezes the player of a disconnected client until it resumes or the grace period ends
+    pub fn suspend(&mut self, model: &mut Model, token: &str) {
+        // Session may have already been taken over by a resumed connection, or kicked
+        self.kicked.remove(token);
+        let session = match self.active.remove(token) {
+            Some(session) => session,
+            None => return,
+        };
+        if let Some(name) = &session.name {
+            info!("{:?} lost connection", name);
+        }
+        model.freeze(session.player_id);
+        self.suspended.insert(
+            token.to_owned(),
+            Suspended {
+                player_id: session.player_id,
+                name: session.name,
+                queued: session.queued,
+                admin: session.admin,
+                muted: session.muted,
+                deadline: model.current_time + Self::RESUME_GRACE,
+            },
+        );
+    }
+
+    /// Moves the session given by `token` to the old player of `old_token`.
+    ///
+    /// The old connection may still look alive when the client notices it is gone first,
+    /// then its session is taken over.
+    pub fn resume(&mut self, model: &mut Model, token: &str, old_token: &str) -> bool {
+        let old = match self.suspended.remove(old_token) {
+            Some(suspended) => suspended,
+            None => match self.active.remove(old_token) {
+                Some(session) => Suspended {
+                    player_id: session.player_id,
+                    name: session.name,
+                    queued: session.queued,
+                    admin: session.admin,
+                    muted: session.muted,
+                    deadline: model.current_time,
+                },
+                None => return false,
+            },
+        };
+        let session = self
+            .active
+            .get_mut(token)
+            .expect("Resuming client has no session");
+        model.disconnect(session.player_id);
+        if let Some(name) = &old.name {
+            info!("{:?} is back", name);
+        }
+        session.player_id = old.player_id;
+        session.name = old.name;
+        session.queued = old.queued;
+        session.admin = old.admin;
+        session.muted = old.muted;
+        true
+    }
+
+    /// Warns, then despawns, then disconnects clients that stopped playing, spectators are left be
+    pub fn check_idle(&mut self, model: &mut Model) {
+        let rules = model.rules.clone();
+        let current_time = model.current_time;
+        let mut idle = Vec::new();
+        for (token, session) in &mut self.active {
+            if model.is_spectating(session.player_id) {
+                session.active_time = current_time;
+                session.idle_stage = 0;
+                continue;
+            }
+            let idle_time = current_time - session.active_time;
+            if session.idle_stage < 1 && idle_time > rules.afk_warn_time {
+                session.idle_stage = 1;
+                session.send(ServerMessage::Notice(
+                    "You seem to be away, move or you will be removed from the game".to_owned(),
+                ));
+            }
+            if session.idle_stage < 2 && idle_time > rules.afk_despawn_time {
+                session.idle_stage = 2;
+                model.despawn(session.player_id);
+                session.send(ServerMessage::Notice(
+                    "You were removed from the world for being away, press R to spawn".to_owned(),
+                ));
+            }
+            if idle_time > rules.afk_disconnect_time {
+                idle.push(token.clone());
+            }
+        }
+        for token in idle {
+            if let Some(name) = &self.active[&token].name {
+                info!("{:?} disconnected for being away", name);
+            }
+            self.reject(
+                model,
+                &token,
+                "You were disconnected for being away too long",
+            );
+        }
+    }
+
+    /// Removes the player of given client and hangs up on it, telling it why
+    pub fn reject(&mut self, model: &mut Model, token: &str, reason: &str) {
+        let mut session = self.active.remove(token).unwrap();
+        model.disconnect(session.player_id);
+        session.send(ServerMessage::Rejected(reason.to_owned()));
+        session.connection.close();
+    }
+
+    /// Throws the client playing as given player off the server
+    pub fn disconnect(&mut self, model: &mut Model, player_id: Id, reason: &str) -> bool {
+        match self.find(player_id) {
+            Some(token) => {
+                self.reject(model, &token, reason);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Throws off clients caught by the bans
+    pub fn reject_banned(&mut self, model: &mut Model, bans: &Bans) {
+        let banned: Vec<(String, String)> = self
+            .active
+            .iter()
+            .filter_map(|(token, session)| {
+                bans.find(session.addr(), Some(token))
+                    .map(|ban| (token.clone(), ban.message()))
+            })
+            .collect();
+        for (token, message) in banned {
+            if let Some(name) = &self.active[&token].name {
+                info!("{:?} is banned", name);
+            }
+            self.reject(model, &token, &message);
+        }
+    }
+
+    /// Tokens of connected and suspended clients, saved with the world
+    pub fn snapshot(&self) -> HashMap<String, SessionSnapshot> {
+        let active = self
+            .active
+            .iter()
+            .map(|(token, session)| (token, session.player_id, session.muted));
+        let suspended = self
+            .suspended
+            .iter()
+            .map(|(token, suspended)| (token, suspended.player_id, suspended.muted));
+        active
+            .chain(suspended)
+            .map(|(token, player_id, muted)| (token.clone(), SessionSnapshot { player_id, muted }))
+            .collect()
+    }
+
+    /// Waits for clients of a restored world to come back, same as after losing connection
+    pub fn restore(&mut self, model: &mut Model, sessions: HashMap<String, SessionSnapshot>) {
+        for (token, session) in sessions {
+            model.freeze(session.player_id);
+            self.suspended.insert(
+                token,
+                Suspended {
+                    player_id: session.player_id,
+                    name: model.player_name(session.player_id).map(str::to_owned),
+                    queued: None,
+                    admin: false,
+                    muted: session.muted,
+                    deadline: model.current_time + Self::RESUME_GRACE,
+                },
+            );
+        }
+    }
+
+    /// Removes players whose clients did not come back in time
+    pub fn expire(&mut self, model: &mut Model) {
+        let current_time = model.current_time;
+        self.suspended.retain(|_, suspended| {
+            if suspended.deadline > current_time {
+                return true;
+            }
+            model.disconnect(suspended.player_id);
+            if let Some(name) = &suspended.name {
+                info!("{:?} disconnected", name);
+            }
+            false
+        });
+    }
+}
@@ -0,0 +1,36 @@
+use super::*;
+
+use common_model::WorldSnapshot;
+
+#[derive(Deserialize)]
+struct SnapshotVersion {
+    version: u32,
+    build: String,
+}
+
+pub fn load(path: &str) -> Result<WorldSnapshot, ConfigError> {
+    let SnapshotVersion { version, build } = config::load_json(path)?;
+    if version != WorldSnapshot::VERSION {
+        return Err(ConfigError::Invalid {
+            path: path.to_owned(),
+            message: format!(
+                "snapshot was saved by build {} with format version {}, expected version {}",
+                build,
+                version,
+                WorldSnapshot::VERSION,
+            ),
+        });
+    }
+    config::load_json(path)
+}
+
+pub fn save(path: &str, snapshot: &WorldSnapshot) {
+    let result = file::write_atomic(path, |writer| {
+        serde_json::to_writer(writer, snapshot)?;
+        Ok(())
+    });
+    match result {
+        Ok(()) => info!("Saved world snapshot to {:?}", path),
+        Err(e) => error!("Failed to save world snapshot: {}", e),
+    }
+}